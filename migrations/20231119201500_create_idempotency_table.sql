@@ -0,0 +1,13 @@
+-- Create Idempotency Table
+CREATE TABLE idempotency(
+    caller_id TEXT NOT NULL,
+    request_method TEXT NOT NULL,
+    request_path TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    response_status_code SMALLINT NULL,
+    response_header_names TEXT[] NULL,
+    response_header_values BYTEA[] NULL,
+    response_body BYTEA NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (caller_id, request_method, request_path, idempotency_key)
+);
@@ -0,0 +1,7 @@
+-- Create Sessions Table
+CREATE TABLE sessions(
+    session_key TEXT NOT NULL,
+    session_state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (session_key)
+);
@@ -0,0 +1,11 @@
+-- Create Email Events Table
+CREATE TABLE email_events(
+    email_event_id uuid NOT NULL,
+    provider TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    email TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    received_at timestamptz NOT NULL,
+    PRIMARY KEY (email_event_id)
+);
+CREATE INDEX email_events_email_idx ON email_events (email);
@@ -0,0 +1,7 @@
+-- Create Suppressed Emails Table
+CREATE TABLE suppressed_emails(
+    email TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    suppressed_at timestamptz NOT NULL,
+    PRIMARY KEY (email)
+);
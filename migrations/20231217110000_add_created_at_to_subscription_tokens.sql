@@ -0,0 +1,4 @@
+-- Add created_at to Subscription Tokens
+-- Tokens issued before this migration start their lifetime now.
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,12 @@
+-- Create Lists Table
+-- Everybody who subscribed before lists existed is on the default one.
+-- Its id is fixed: `gen_random_uuid()` needs Postgres 13, we still run on 12.
+CREATE TABLE lists(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+INSERT INTO lists (id, slug, name, created_at)
+VALUES ('b6f1c6a2-5b2e-4c59-9d3e-2f1a8c7e4d01', 'newsletter', 'Newsletter', now());
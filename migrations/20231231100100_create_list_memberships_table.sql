@@ -0,0 +1,16 @@
+-- Create List Memberships Table
+-- The confirmation status now belongs to each list a subscriber joined.
+CREATE TABLE list_memberships(
+    list_id uuid NOT NULL
+        REFERENCES lists (id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id, subscriber_id)
+);
+INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+SELECT lists.id, subscriptions.id, subscriptions.status, subscriptions.subscribed_at
+FROM subscriptions, lists
+WHERE lists.slug = 'newsletter';
+ALTER TABLE subscriptions DROP COLUMN status;
@@ -0,0 +1,10 @@
+-- Scope Subscription Tokens and Newsletter Issues to a list
+-- Existing ones were all issued for the default list.
+ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL REFERENCES lists (id);
+ALTER TABLE newsletter_issues ADD COLUMN list_id uuid NULL REFERENCES lists (id);
+UPDATE subscription_tokens
+    SET list_id = (SELECT id FROM lists WHERE slug = 'newsletter');
+UPDATE newsletter_issues
+    SET list_id = (SELECT id FROM lists WHERE slug = 'newsletter');
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN list_id SET NOT NULL;
@@ -0,0 +1,25 @@
+-- Create Tenants Table
+-- Everything that predates tenants belongs to the default one, which falls back
+-- to the sender and credentials of the deployment's configuration.
+CREATE TABLE tenants(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    slug TEXT NOT NULL UNIQUE,
+    host TEXT NULL UNIQUE,
+    base_url TEXT NULL,
+    sender_email TEXT NULL,
+    email_authorization_token TEXT NULL,
+    created_at timestamptz NOT NULL
+);
+-- Its id is fixed: `gen_random_uuid()` needs Postgres 13, we still run on 12.
+INSERT INTO tenants (id, slug, created_at)
+VALUES ('5d0c7f3e-8a41-4b6e-9f2d-71c3a9e0b512', 'default', now());
+-- The users allowed to act on behalf of each tenant through the API.
+-- Everybody who could use it before tenants existed works for the default one.
+CREATE TABLE tenant_members(
+    tenant_id uuid NOT NULL REFERENCES tenants (id),
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    PRIMARY KEY (tenant_id, user_id)
+);
+INSERT INTO tenant_members (tenant_id, user_id)
+SELECT tenants.id, users.user_id FROM tenants, users;
@@ -0,0 +1,18 @@
+-- Scope Lists, Subscriptions and Suppressed Emails to a tenant
+-- Slugs and email addresses only have to be unique within a tenant.
+ALTER TABLE lists ADD COLUMN tenant_id uuid NULL REFERENCES tenants (id);
+ALTER TABLE subscriptions ADD COLUMN tenant_id uuid NULL REFERENCES tenants (id);
+UPDATE lists SET tenant_id = (SELECT id FROM tenants WHERE slug = 'default');
+UPDATE subscriptions SET tenant_id = (SELECT id FROM tenants WHERE slug = 'default');
+ALTER TABLE lists ALTER COLUMN tenant_id SET NOT NULL;
+ALTER TABLE subscriptions ALTER COLUMN tenant_id SET NOT NULL;
+ALTER TABLE lists DROP CONSTRAINT lists_slug_key;
+ALTER TABLE lists ADD CONSTRAINT lists_tenant_id_slug_key UNIQUE (tenant_id, slug);
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_tenant_id_email_key UNIQUE (tenant_id, email);
+-- An address that bounced for one tenant may still be reachable for another.
+ALTER TABLE suppressed_emails ADD COLUMN tenant_id uuid NULL REFERENCES tenants (id);
+UPDATE suppressed_emails SET tenant_id = (SELECT id FROM tenants WHERE slug = 'default');
+ALTER TABLE suppressed_emails ALTER COLUMN tenant_id SET NOT NULL;
+ALTER TABLE suppressed_emails DROP CONSTRAINT suppressed_emails_pkey;
+ALTER TABLE suppressed_emails ADD PRIMARY KEY (tenant_id, email);
@@ -0,0 +1,13 @@
+-- Create Topics Table
+-- Issues may be about one of the topics of their list, subscribers may only want some of them.
+CREATE TABLE topics(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    list_id uuid NOT NULL
+        REFERENCES lists (id),
+    slug TEXT NOT NULL,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    UNIQUE (list_id, slug)
+);
+ALTER TABLE newsletter_issues ADD COLUMN topic_id uuid NULL REFERENCES topics (id);
@@ -0,0 +1,19 @@
+-- Create Subscriber Preferences Tables
+-- Subscribers who never saved their preferences get every issue, as soon as it is published.
+-- Topics are opted out of, so that subscribers get the ones created after they saved them.
+CREATE TABLE subscriber_preferences(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    PRIMARY KEY (subscriber_id),
+    delivery_frequency TEXT NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+CREATE TABLE subscriber_topic_opt_outs(
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    topic_id uuid NOT NULL
+        REFERENCES topics (id),
+    PRIMARY KEY (subscriber_id, topic_id)
+);
+-- Deliveries to weekly-digest subscribers wait for the next digest, then go out in a single email.
+ALTER TABLE issue_delivery_queue ADD COLUMN digest BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,4 @@
+-- Add Erased At To Subscriptions
+-- Erased subscribers keep their row, stripped of anything personal, so that the counts
+-- of subscriptions over time stay right.
+ALTER TABLE subscriptions ADD COLUMN erased_at timestamptz NULL;
@@ -0,0 +1,9 @@
+-- Short-lived tokens, emailed on request, that authorise a single kind of
+-- data-subject request: `export` or `erasure`.
+CREATE TABLE subscriber_data_tokens(
+    token TEXT PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    purpose TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX subscriber_data_tokens_subscriber_id_idx ON subscriber_data_tokens (subscriber_id);
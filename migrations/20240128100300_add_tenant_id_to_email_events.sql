@@ -0,0 +1,11 @@
+-- Events are attributed to the tenant named in the metadata of the email they are about.
+ALTER TABLE email_events ADD COLUMN tenant_id uuid NULL REFERENCES tenants (id);
+-- Until a second tenant existed, every email was sent on behalf of the default one.
+-- Later events cannot be attributed: they go along with the data of every tenant the address subscribed to.
+UPDATE email_events
+SET tenant_id = (SELECT id FROM tenants WHERE slug = 'default')
+WHERE received_at < COALESCE(
+    (SELECT min(created_at) FROM tenants WHERE slug <> 'default'),
+    'infinity'
+);
+CREATE INDEX email_events_tenant_id_email_idx ON email_events (tenant_id, email);
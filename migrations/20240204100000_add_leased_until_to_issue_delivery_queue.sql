@@ -0,0 +1,3 @@
+-- Tasks are hidden from other workers while their emails are being sent,
+-- rather than kept locked for the duration of the network calls.
+ALTER TABLE issue_delivery_queue ADD COLUMN leased_until timestamptz NULL;
@@ -0,0 +1,111 @@
+use actix_web::{http::header::HeaderMap, HttpMessage, HttpRequest};
+use anyhow::Context;
+use base64::Engine;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{validate_credentials, AuthError, Credentials};
+
+/// What came out of checking the Basic credentials of a request, kept in its extensions.
+#[derive(Clone, Copy)]
+enum BasicAuthOutcome {
+    Authenticated(Uuid),
+    Rejected,
+}
+
+/// Return the id of the user whose Basic credentials the request carries,
+/// provided they are a member of the tenant the request is about.
+#[tracing::instrument(name = "Authenticate with Basic credentials", skip(request, pool))]
+pub async fn authenticate_basic(
+    request: &HttpRequest,
+    tenant_id: Uuid,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let user_id = identify_basic(request, pool).await?;
+    let is_member = sqlx::query!(
+        r#"
+            SELECT EXISTS(
+                SELECT 1 FROM tenant_members WHERE tenant_id = $1 AND user_id = $2
+            ) AS "is_member!"
+        "#,
+        tenant_id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up the tenants of the user.")?
+    .is_member;
+    if !is_member {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "The user is not a member of the tenant."
+        )));
+    }
+    Ok(user_id)
+}
+
+/// Return the id of the user whose Basic credentials the request carries,
+/// whichever tenants they are a member of.
+///
+/// Middleware may need to know the caller before the handler runs: the outcome is
+/// stored on the request, so that the password hash is only verified once.
+#[tracing::instrument(
+    name = "Identify the user of Basic credentials",
+    skip_all,
+    fields(username = tracing::field::Empty)
+)]
+pub async fn identify_basic(request: &HttpRequest, pool: &PgPool) -> Result<Uuid, AuthError> {
+    if let Some(outcome) = request.extensions().get::<BasicAuthOutcome>().copied() {
+        return match outcome {
+            BasicAuthOutcome::Authenticated(user_id) => Ok(user_id),
+            BasicAuthOutcome::Rejected => Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                "The credentials were already rejected."
+            ))),
+        };
+    }
+    let outcome = match basic_authentication(request.headers()) {
+        Ok(credentials) => {
+            tracing::Span::current()
+                .record("username", tracing::field::display(&credentials.username));
+            validate_credentials(credentials, pool).await
+        }
+        Err(e) => Err(AuthError::InvalidCredentials(e)),
+    };
+    match &outcome {
+        Ok(user_id) => {
+            request
+                .extensions_mut()
+                .insert(BasicAuthOutcome::Authenticated(*user_id));
+        }
+        Err(AuthError::InvalidCredentials(_)) => {
+            request.extensions_mut().insert(BasicAuthOutcome::Rejected);
+        }
+        // Worth another try.
+        Err(AuthError::UnexpectedError(_)) => {}
+    }
+    outcome
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth.")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
@@ -0,0 +1,78 @@
+use std::ops::Deref;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::StatusCode,
+    FromRequest, HttpMessage, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use uuid::Uuid;
+
+use crate::{
+    problem_details::ProblemDetails,
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Redirect to the login form unless the session belongs to a logged-in user.
+/// The user id is made available to handlers as a `web::ReqData<UserId>`.
+pub async fn reject_anonymous_users(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_login(req, next, || see_other("/login")).await
+}
+
+/// Like `reject_anonymous_users`, for API clients: they can't follow a redirect
+/// to a form, so they get a 401 instead.
+pub async fn reject_anonymous_api_clients(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_login(req, next, || {
+        ProblemDetails::new(StatusCode::UNAUTHORIZED, "/problems/not-logged-in")
+            .with_title("You must be logged in.")
+            .to_response()
+    })
+    .await
+}
+
+async fn require_login<B: MessageBody>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+    rejection: impl FnOnce() -> HttpResponse,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    match session.get_user_id().map_err(e500)? {
+        Some(user_id) => {
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await
+        }
+        None => {
+            let e = anyhow::anyhow!("The user has not logged in");
+            Err(actix_web::error::InternalError::from_response(e, rejection()).into())
+        }
+    }
+}
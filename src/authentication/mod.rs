@@ -0,0 +1,9 @@
+mod basic;
+mod middleware;
+mod password;
+
+pub use basic::{authenticate_basic, identify_basic};
+pub use middleware::{reject_anonymous_api_clients, reject_anonymous_users, UserId};
+pub use password::{
+    change_password, provision_admin, validate_credentials, AuthError, Credentials,
+};
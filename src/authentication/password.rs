@@ -0,0 +1,192 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{metrics::begin_transaction, telemetry::spawn_blocking_with_tracing, tenancy::Tenant};
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Return the id of the user the credentials belong to.
+///
+/// Unknown usernames are verified against a dummy hash, so that the time it takes to
+/// reject them does not reveal which usernames exist.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        UTM2EhDOn/JFuHicj5XI2g$\
+        JpGwLnFAauwg22ccYGJB07O5CiX6v1Pf67auBHxDWkQ"
+            .to_string(),
+    );
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, AuthError> {
+    let row = sqlx::query!(
+        r#"
+            SELECT user_id, password_hash
+            FROM users
+            WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    Ok(row)
+}
+
+#[tracing::instrument(name = "Change password", skip(password, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password.")?;
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET password_hash = $1
+            WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+/// Create the first administrator from credentials supplied out of band.
+///
+/// Does nothing if a user with the same username already exists: their password
+/// may have been changed since, and must not be reset on every start.
+/// The admin acts on behalf of the default tenant.
+#[tracing::instrument(name = "Provision admin", skip(credentials, pool), fields(username = %credentials.username))]
+pub async fn provision_admin(credentials: Credentials, pool: &PgPool) -> Result<(), anyhow::Error> {
+    // Hashing is deliberately slow: don't pay for it on every start.
+    let already_exists = sqlx::query!(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE username = $1) AS \"exists!\"",
+        credentials.username,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up the admin user.")?
+    .exists;
+    if already_exists {
+        return Ok(());
+    }
+    let password = credentials.password;
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password.")?;
+    let user_id = Uuid::new_v4();
+    let mut transaction = begin_transaction(pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let inserted = sqlx::query!(
+        r#"
+            INSERT INTO users (user_id, username, password_hash)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        credentials.username,
+        password_hash.expose_secret(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the admin user.")?
+    .rows_affected();
+    if inserted > 0 {
+        // Other tenants get their own members.
+        sqlx::query!(
+            r#"
+                INSERT INTO tenant_members (tenant_id, user_id)
+                SELECT id, $1 FROM tenants WHERE slug = $2
+            "#,
+            user_id,
+            Tenant::DEFAULT
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to make the admin user a member of the default tenant.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store the admin user.")?;
+    Ok(())
+}
+
+fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
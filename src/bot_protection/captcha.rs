@@ -0,0 +1,26 @@
+/// Checks the answer a CAPTCHA widget added to the subscription form.
+#[async_trait::async_trait]
+pub trait CaptchaVerifier: std::fmt::Debug + Send + Sync {
+    /// Whether `response`, as submitted along with the form, proves that
+    /// a human solved the challenge.
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Accepts a single, well-known solution: for tests and local development only.
+#[derive(Debug)]
+pub struct FakeCaptchaVerifier {
+    solution: String,
+}
+
+impl FakeCaptchaVerifier {
+    pub fn new(solution: String) -> Self {
+        Self { solution }
+    }
+}
+
+#[async_trait::async_trait]
+impl CaptchaVerifier for FakeCaptchaVerifier {
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        Ok(response == self.solution)
+    }
+}
@@ -0,0 +1,245 @@
+mod captcha;
+
+use std::sync::Arc;
+
+pub use captcha::{CaptchaVerifier, FakeCaptchaVerifier};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+
+use crate::domain::FormToken;
+
+/// Tells automated signups apart from the ones made by people filling in the form.
+#[derive(Debug, Clone)]
+pub struct BotProtection {
+    form_token_secret: Secret<String>,
+    require_form_token: bool,
+    min_fill_time: chrono::Duration,
+    max_form_age: chrono::Duration,
+    captcha: Option<Arc<dyn CaptchaVerifier>>,
+}
+
+/// What a submission carries besides the subscriber details.
+#[derive(Debug, Default)]
+pub struct BotSignals<'a> {
+    /// A field hidden from people: only bots fill it in.
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub captcha_response: Option<&'a str>,
+    /// Who submitted the form, if we can tell: form tokens are bound to it.
+    pub client_ip: Option<&'a str>,
+    /// Whether the submission was posted by the form of `subscription_form`.
+    /// Only those carry a form token: API clients never get one.
+    pub from_form: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Human,
+    /// Accept the submission, but do nothing with it: bots that learn they
+    /// were caught adapt.
+    Bot {
+        reason: &'static str,
+    },
+    /// The form was rendered too long ago: a person can reload it and try again.
+    ExpiredForm,
+    /// A CAPTCHA is required and was missing or wrong.
+    CaptchaFailed,
+}
+
+impl BotProtection {
+    pub fn new(
+        form_token_secret: Secret<String>,
+        require_form_token: bool,
+        min_fill_time: chrono::Duration,
+        max_form_age: chrono::Duration,
+    ) -> Self {
+        Self {
+            form_token_secret,
+            require_form_token,
+            min_fill_time,
+            max_form_age,
+            captcha: None,
+        }
+    }
+
+    pub fn with_captcha(mut self, captcha: impl CaptchaVerifier + 'static) -> Self {
+        self.captcha = Some(Arc::new(captcha));
+        self
+    }
+
+    /// The token to embed in a form rendered now for `client_ip`.
+    pub fn form_token(&self, client_ip: Option<&str>) -> FormToken {
+        FormToken::generate(
+            Utc::now(),
+            client_ip.unwrap_or_default(),
+            &self.form_token_secret,
+        )
+    }
+
+    #[tracing::instrument(name = "Check for signs of automation", skip_all, ret, err)]
+    pub async fn check(&self, signals: &BotSignals<'_>) -> Result<Verdict, anyhow::Error> {
+        if signals.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Ok(Verdict::Bot {
+                reason: "filled the honeypot field",
+            });
+        }
+        match signals.form_token {
+            Some(token) => match FormToken::verify(
+                token,
+                signals.client_ip.unwrap_or_default(),
+                &self.form_token_secret,
+            ) {
+                Ok(issued_at) => {
+                    if let Some(verdict) = self.check_fill_time(issued_at, Utc::now()) {
+                        return Ok(verdict);
+                    }
+                }
+                Err(_) => {
+                    return Ok(Verdict::Bot {
+                        reason: "forged the form token or replayed it from elsewhere",
+                    })
+                }
+            },
+            None if self.require_form_token && signals.from_form => {
+                return Ok(Verdict::Bot {
+                    reason: "did not submit a form token",
+                })
+            }
+            None => {}
+        }
+        if let Some(captcha) = &self.captcha {
+            let solved = match signals.captcha_response {
+                Some(response) => captcha
+                    .verify(response)
+                    .await
+                    .context("Failed to verify the CAPTCHA response.")?,
+                None => false,
+            };
+            if !solved {
+                return Ok(Verdict::CaptchaFailed);
+            }
+        }
+        Ok(Verdict::Human)
+    }
+
+    fn check_fill_time(&self, issued_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<Verdict> {
+        let fill_time = now - issued_at;
+        if fill_time < self.min_fill_time {
+            Some(Verdict::Bot {
+                reason: "submitted the form too quickly",
+            })
+        } else if fill_time > self.max_form_age {
+            Some(Verdict::ExpiredForm)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use secrecy::Secret;
+
+    use super::{BotProtection, BotSignals, FakeCaptchaVerifier, Verdict};
+    use crate::domain::FormToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-long-and-very-secret-key".to_string())
+    }
+
+    fn protection() -> BotProtection {
+        BotProtection::new(secret(), false, Duration::seconds(3), Duration::hours(1))
+    }
+
+    const CLIENT_IP: &str = "203.0.113.1";
+
+    fn token_issued(ago: Duration) -> FormToken {
+        FormToken::generate(Utc::now() - ago, CLIENT_IP, &secret())
+    }
+
+    #[tokio::test]
+    async fn a_filled_honeypot_gives_bots_away() {
+        let signals = BotSignals {
+            honeypot: Some("https://spam.example.com"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            protection().check(&signals).await.unwrap(),
+            Verdict::Bot { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn submissions_faster_than_the_minimum_fill_time_are_from_bots() {
+        let token = token_issued(Duration::seconds(1));
+        let signals = BotSignals {
+            form_token: Some(token.as_ref()),
+            client_ip: Some(CLIENT_IP),
+            ..Default::default()
+        };
+        assert!(matches!(
+            protection().check(&signals).await.unwrap(),
+            Verdict::Bot { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn forms_rendered_too_long_ago_have_expired() {
+        let token = token_issued(Duration::hours(2));
+        let signals = BotSignals {
+            form_token: Some(token.as_ref()),
+            client_ip: Some(CLIENT_IP),
+            ..Default::default()
+        };
+        assert_eq!(
+            protection().check(&signals).await.unwrap(),
+            Verdict::ExpiredForm
+        );
+    }
+
+    #[tokio::test]
+    async fn a_form_token_is_only_mandatory_if_required() {
+        let signals = BotSignals {
+            from_form: true,
+            ..Default::default()
+        };
+        assert_eq!(protection().check(&signals).await.unwrap(), Verdict::Human);
+
+        let protection = BotProtection::new(secret(), true, Duration::zero(), Duration::hours(1));
+        assert!(matches!(
+            protection.check(&signals).await.unwrap(),
+            Verdict::Bot { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_form_token_is_not_expected_from_api_clients() {
+        let protection = BotProtection::new(secret(), true, Duration::zero(), Duration::hours(1));
+        assert_eq!(
+            protection.check(&BotSignals::default()).await.unwrap(),
+            Verdict::Human
+        );
+    }
+
+    #[tokio::test]
+    async fn the_captcha_must_be_solved_when_enabled() {
+        let protection = protection().with_captcha(FakeCaptchaVerifier::new("solved".to_string()));
+        let token = token_issued(Duration::minutes(1));
+        for (captcha_response, expected) in [
+            (None, Verdict::CaptchaFailed),
+            (Some("wrong"), Verdict::CaptchaFailed),
+            (Some("solved"), Verdict::Human),
+        ] {
+            let signals = BotSignals {
+                form_token: Some(token.as_ref()),
+                captcha_response,
+                client_ip: Some(CLIENT_IP),
+                ..Default::default()
+            };
+            assert_eq!(protection.check(&signals).await.unwrap(), expected);
+        }
+    }
+}
@@ -1,25 +1,133 @@
+use std::collections::HashMap;
+
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
-    ConnectOptions,
+    ConnectOptions, PgPool,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    bot_protection::{BotProtection, FakeCaptchaVerifier},
+    domain::SubscriberEmail,
+    email_client::{
+        EmailClient, FileOutboxTransport, PostmarkTransport, RetryPolicy, SmtpTransport,
+    },
+    rate_limiting::{InMemoryRateLimitStore, PgRateLimitStore, RateLimiter, TokenBucket},
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub issue_delivery_worker: IssueDeliveryWorkerSettings,
+    pub subscription_tokens: SubscriptionTokenSettings,
+    pub idempotency: IdempotencySettings,
+    pub health: HealthSettings,
+    pub telemetry: TelemetrySettings,
+    pub shutdown: ShutdownSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// Shared with the provider, which sends it along with every webhook call.
+    pub webhook_secret: Secret<String>,
+    pub retry: EmailClientRetrySettings,
+    pub smtp: SmtpSettings,
+    pub file_outbox: FileOutboxSettings,
+}
+
+/// Which backend `EmailClient` hands its emails over to.
+/// `base_url`, `authorization_token` and `retry` only apply to Postmark.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailProvider {
+    Postmark,
+    Smtp,
+    FileOutbox,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub require_tls: bool,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileOutboxSettings {
+    pub directory: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailClientRetrySettings {
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub retryable_status_codes: Vec<u16>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IssueDeliveryWorkerSettings {
+    pub idle_poll_interval_milliseconds: u64,
+    pub max_attempts: i16,
+    pub retry_delay_seconds: u64,
+    /// How many subscribers of an issue to hand over to the email provider at once.
+    pub batch_size: i64,
+    /// How long tasks being delivered are hidden from other workers.
+    /// They are picked up again if the worker dies before settling them.
+    pub lease_seconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionTokenSettings {
+    pub ttl_hours: u64,
+    /// How long the link to export or erase one's data stays valid.
+    pub data_request_ttl_minutes: u64,
+    pub cleanup_interval_minutes: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IdempotencySettings {
+    /// How long a saved response is replayed for, before its key can be reused.
+    pub ttl_hours: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct HealthSettings {
+    pub timeout_milliseconds: u64,
+    /// Probing the provider costs an API call on every readiness check.
+    pub probe_email_provider: bool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    /// Export spans to an OpenTelemetry collector, on top of the bunyan logs.
+    pub otlp_enabled: bool,
+    /// Base URL of the collector's OTLP/HTTP receiver.
+    pub otlp_endpoint: String,
+    pub service_name: String,
+    /// Fraction of new traces to record, between 0 and 1.
+    /// Requests joining an existing trace follow the caller's decision.
+    pub sampling_ratio: f64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ShutdownSettings {
+    /// How long to keep serving after readiness starts failing, so that the
+    /// load balancer has time to stop routing new requests to this instance.
+    pub grace_period_milliseconds: u64,
+    /// Upper bound on the time in-flight requests and background jobs get to finish.
+    pub drain_timeout_seconds: u64,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -38,6 +146,78 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u32,
     pub host: String,
+    pub base_url: String,
+    pub unsubscribe_secret: Secret<String>,
+    pub hmac_secret: Secret<String>,
+    /// Created on start-up if no user has this username yet.
+    /// Supply it through `APP_APPLICATION__INITIAL_ADMIN__*` rather than a committed file.
+    pub initial_admin: Option<InitialAdminSettings>,
+    pub rate_limiting: RateLimitingSettings,
+    pub bot_protection: BotProtectionSettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct InitialAdminSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Signs the timestamp embedded in the subscription form.
+    pub form_token_secret: Secret<String>,
+    /// Treat submissions of the subscription form without a form token as automated.
+    /// Only disable it while forms posting to us don't embed the token yet.
+    /// JSON and per-list signups are never expected to carry one.
+    pub require_form_token: bool,
+    /// Nobody fills the form in faster than this.
+    pub min_fill_time_seconds: u64,
+    /// Older forms must be reloaded before being submitted.
+    /// Kept short: until then, the token can be replayed by the same client.
+    pub max_form_age_minutes: u64,
+    pub captcha: CaptchaSettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CaptchaSettings {
+    pub provider: CaptchaProvider,
+    /// The only response accepted by the fake provider.
+    pub fake_solution: String,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    Disabled,
+    /// Not a real CAPTCHA: for tests and local development.
+    Fake,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitingSettings {
+    pub backend: RateLimitBackend,
+    /// How many proxies of ours, each appending to `X-Forwarded-For`, requests go
+    /// through. Clients are identified by the hop the outermost one appended,
+    /// or by the peer address if there are none or fewer hops than proxies.
+    pub trusted_proxies: usize,
+    /// Limits per client IP, keyed by route pattern (e.g. `/subscriptions`).
+    pub routes: HashMap<String, RateLimitSettings>,
+    /// Limit on the confirmation emails sent to the same address.
+    pub per_email: RateLimitSettings,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    InMemory,
+    /// Shares the limits between all instances of the application.
+    Postgres,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub capacity: u32,
+    pub refill_interval_seconds: u64,
 }
 
 impl DatabaseSettings {
@@ -55,7 +235,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -75,9 +255,65 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         config::File::from(configuration_directory.join(environment.as_str())).required(true),
     )?;
     settings.merge(config::Environment::with_prefix("app").separator("__"))?;
+    if let Environment::Production = environment {
+        let mut base = config::Config::default();
+        base.merge(config::File::from(configuration_directory.join("base")).required(true))?;
+        ensure_secrets_are_set(&settings, &base)?;
+    }
+    ensure_hmac_secret_is_long_enough(&settings)?;
     settings.try_into()
 }
 
+/// The session and flash message cookies are signed with a key derived from it,
+/// which can't be built from fewer bytes.
+const MIN_HMAC_SECRET_LENGTH: usize = 64;
+
+fn ensure_hmac_secret_is_long_enough(settings: &config::Config) -> Result<(), config::ConfigError> {
+    let length = settings.get_str("application.hmac_secret")?.len();
+    if length >= MIN_HMAC_SECRET_LENGTH {
+        return Ok(());
+    }
+    Err(config::ConfigError::Message(format!(
+        "APP_APPLICATION__HMAC_SECRET must be at least {} bytes long, not {}.",
+        MIN_HMAC_SECRET_LENGTH, length
+    )))
+}
+
+/// Secrets that `base.yaml` gives a value to, for local development.
+// The webhook endpoint is served whichever provider sends the emails.
+const SECRETS: &[&str] = &[
+    "application.unsubscribe_secret",
+    "application.hmac_secret",
+    "email_client.webhook_secret",
+    "application.bot_protection.form_token_secret",
+];
+
+/// Only needed when Postmark delivers the emails.
+const POSTMARK_SECRETS: &[&str] = &["email_client.authorization_token"];
+
+/// Refuse to run with secrets that anyone reading the repository knows.
+fn ensure_secrets_are_set(
+    settings: &config::Config,
+    base: &config::Config,
+) -> Result<(), config::ConfigError> {
+    let uses_postmark =
+        settings.get_str("email_client.provider").ok().as_deref() == Some("postmark");
+    let provider_secrets = if uses_postmark { POSTMARK_SECRETS } else { &[] };
+    let placeholders: Vec<_> = SECRETS
+        .iter()
+        .chain(provider_secrets)
+        .filter(|key| settings.get_str(key).ok() == base.get_str(key).ok())
+        .map(|key| format!("APP_{}", key.replace('.', "__").to_uppercase()))
+        .collect();
+    if placeholders.is_empty() {
+        return Ok(());
+    }
+    Err(config::ConfigError::Message(format!(
+        "These secrets still have the values committed in base.yaml: {}.",
+        placeholders.join(", ")
+    )))
+}
+
 pub enum Environment {
     Local,
     Production,
@@ -108,6 +344,37 @@ impl TryFrom<String> for Environment {
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        match self.provider {
+            EmailProvider::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(
+                    self.base_url,
+                    self.authorization_token,
+                    timeout,
+                    self.retry.policy(),
+                ),
+            ),
+            EmailProvider::Smtp => {
+                let credentials = self.smtp.username.zip(self.smtp.password);
+                let transport = SmtpTransport::new(
+                    &self.smtp.host,
+                    self.smtp.port,
+                    self.smtp.require_tls,
+                    credentials,
+                    timeout,
+                )
+                .expect("Invalid SMTP relay configuration.");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailProvider::FileOutbox => EmailClient::new(
+                sender_email,
+                FileOutboxTransport::new(self.file_outbox.directory),
+            ),
+        }
+    }
 
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
@@ -117,3 +384,187 @@ impl EmailClientSettings {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 }
+
+impl EmailClientRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            retryable_status_codes: self.retryable_status_codes.clone(),
+        }
+    }
+}
+
+impl IssueDeliveryWorkerSettings {
+    pub fn idle_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.idle_poll_interval_milliseconds)
+    }
+
+    pub fn lease(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lease_seconds)
+    }
+
+    /// How long to wait before retrying a task that already failed `n_retries` times.
+    pub fn retry_delay(&self, n_retries: i16) -> std::time::Duration {
+        let factor = 2u32.saturating_pow(n_retries.max(0) as u32);
+        std::time::Duration::from_secs(self.retry_delay_seconds).saturating_mul(factor)
+    }
+}
+
+impl SubscriptionTokenSettings {
+    /// How long a confirmation link stays valid after it has been sent.
+    pub fn ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.ttl_hours as i64)
+    }
+
+    pub fn data_request_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.data_request_ttl_minutes as i64)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_minutes * 60)
+    }
+}
+
+impl HealthSettings {
+    /// How long each readiness check may take before the component is reported as down.
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl ShutdownSettings {
+    pub fn grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.grace_period_milliseconds)
+    }
+
+    pub fn drain_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.drain_timeout_seconds)
+    }
+}
+
+impl BotProtectionSettings {
+    pub fn protection(self) -> BotProtection {
+        let protection = BotProtection::new(
+            self.form_token_secret,
+            self.require_form_token,
+            chrono::Duration::seconds(self.min_fill_time_seconds as i64),
+            chrono::Duration::minutes(self.max_form_age_minutes as i64),
+        );
+        match self.captcha.provider {
+            CaptchaProvider::Disabled => protection,
+            CaptchaProvider::Fake => {
+                protection.with_captcha(FakeCaptchaVerifier::new(self.captcha.fake_solution))
+            }
+        }
+    }
+}
+
+impl RateLimitingSettings {
+    /// `email_key_secret` signs the keys of the per-recipient buckets.
+    pub fn limiter(&self, pool: PgPool, email_key_secret: Secret<String>) -> RateLimiter {
+        match self.backend {
+            RateLimitBackend::InMemory => {
+                RateLimiter::new(InMemoryRateLimitStore::new(), self, email_key_secret)
+            }
+            RateLimitBackend::Postgres => {
+                RateLimiter::new(PgRateLimitStore::new(pool), self, email_key_secret)
+            }
+        }
+    }
+
+    /// Past this long without being used, any bucket is full again and can be forgotten.
+    pub fn longest_time_to_fill(&self) -> std::time::Duration {
+        self.routes
+            .values()
+            .chain(std::iter::once(&self.per_email))
+            .map(|limit| limit.bucket().time_to_fill())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+impl RateLimitSettings {
+    pub fn bucket(&self) -> TokenBucket {
+        TokenBucket {
+            capacity: self.capacity,
+            refill_interval: std::time::Duration::from_secs(self.refill_interval_seconds),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ensure_hmac_secret_is_long_enough, ensure_secrets_are_set, MIN_HMAC_SECRET_LENGTH,
+        POSTMARK_SECRETS, SECRETS,
+    };
+    use claim::assert_ok;
+    use config::Config;
+
+    fn all_secrets() -> Vec<&'static str> {
+        SECRETS.iter().chain(POSTMARK_SECRETS).copied().collect()
+    }
+
+    /// Postmark delivers the emails, and every secret is set to `committed`
+    /// but those in `overridden`.
+    fn config(overridden: &[&str]) -> Config {
+        let mut config = Config::default();
+        config.set("email_client.provider", "postmark").unwrap();
+        for key in all_secrets() {
+            let value = if overridden.contains(&key) {
+                "overridden"
+            } else {
+                "committed"
+            };
+            config.set(key, value).unwrap();
+        }
+        config
+    }
+
+    #[test]
+    fn committed_secrets_are_rejected() {
+        let secrets = all_secrets();
+        for key in &secrets {
+            let others: Vec<_> = secrets.iter().copied().filter(|k| k != key).collect();
+            let err = ensure_secrets_are_set(&config(&others), &config(&[])).unwrap_err();
+            let variable = format!("APP_{}", key.replace('.', "__").to_uppercase());
+            assert!(err.to_string().contains(&variable), "{} was accepted", key);
+        }
+    }
+
+    #[test]
+    fn overridden_secrets_are_accepted() {
+        assert_ok!(ensure_secrets_are_set(
+            &config(&all_secrets()),
+            &config(&[])
+        ));
+    }
+
+    #[test]
+    fn postmark_secrets_are_only_required_with_postmark() {
+        let mut settings = config(SECRETS);
+        settings.set("email_client.provider", "smtp").unwrap();
+        assert_ok!(ensure_secrets_are_set(&settings, &config(&[])));
+    }
+
+    #[test]
+    fn short_hmac_secrets_are_rejected() {
+        let mut settings = Config::default();
+        for (length, accepted) in [
+            (MIN_HMAC_SECRET_LENGTH - 1, false),
+            (MIN_HMAC_SECRET_LENGTH, true),
+        ] {
+            settings
+                .set("application.hmac_secret", "a".repeat(length))
+                .unwrap();
+            assert_eq!(
+                ensure_hmac_secret_is_long_enough(&settings).is_ok(),
+                accepted,
+                "Length: {}",
+                length
+            );
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::{future::Future, ops::Deref, pin::Pin};
+
+use actix_web::{
+    dev::Payload,
+    http::header::{self, Header},
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+use serde::de::DeserializeOwned;
+
+/// A request body sent either by a browser form or by an API client as JSON,
+/// depending on its `Content-Type`. Anything that is not JSON is parsed as a form,
+/// so that the errors browsers get are unchanged.
+#[derive(Debug)]
+pub struct JsonOrForm<T>(pub T);
+
+impl<T> JsonOrForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for JsonOrForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for JsonOrForm<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if is_json(req) {
+            let json = web::Json::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(Self(json.await?.into_inner())) })
+        } else {
+            let form = web::Form::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(Self(form.await?.into_inner())) })
+        }
+    }
+}
+
+pub fn is_json(req: &HttpRequest) -> bool {
+    matches!(
+        req.mime_type(),
+        Ok(Some(mime)) if mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON)
+    )
+}
+
+/// How to render a successful response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// What browsers have always been getting: a status code and nothing else.
+    Empty,
+    Json,
+}
+
+/// Scopes can pin the format with `app_data`, e.g. for a JSON-only API.
+/// Otherwise, clients get JSON if they prefer it over anything else.
+impl FromRequest for ResponseFormat {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let format = match req.app_data::<ResponseFormat>() {
+            Some(format) => *format,
+            None => negotiate(req),
+        };
+        std::future::ready(Ok(format))
+    }
+}
+
+fn negotiate(req: &HttpRequest) -> ResponseFormat {
+    let preferred = header::Accept::parse(req)
+        .ok()
+        .and_then(|accept| accept.ranked().into_iter().next());
+    match preferred {
+        Some(mime) if mime.subtype() == mime::JSON => ResponseFormat::Json,
+        _ => ResponseFormat::Empty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test::TestRequest, FromRequest};
+
+    use super::{JsonOrForm, ResponseFormat};
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Data {
+        name: String,
+    }
+
+    #[actix_web::test]
+    async fn json_bodies_are_parsed_as_json() {
+        let (req, mut payload) = TestRequest::post()
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(r#"{"name": "Ursula"}"#)
+            .to_http_parts();
+        let data = JsonOrForm::<Data>::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+        assert_eq!(data.name, "Ursula");
+    }
+
+    #[actix_web::test]
+    async fn other_bodies_are_parsed_as_forms() {
+        let (req, mut payload) = TestRequest::post()
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .set_payload("name=Ursula")
+            .to_http_parts();
+        let data = JsonOrForm::<Data>::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+        assert_eq!(data.name, "Ursula");
+    }
+
+    #[actix_web::test]
+    async fn json_is_returned_to_clients_preferring_it() {
+        let test_cases = vec![
+            (None, ResponseFormat::Empty),
+            (Some("application/json"), ResponseFormat::Json),
+            (
+                Some("application/json, text/plain, */*"),
+                ResponseFormat::Json,
+            ),
+            (
+                Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                ResponseFormat::Empty,
+            ),
+            (
+                Some("text/html;q=0.5, application/json"),
+                ResponseFormat::Json,
+            ),
+        ];
+        for (accept, expected) in test_cases {
+            let mut req = TestRequest::post();
+            if let Some(accept) = accept {
+                req = req.insert_header(("Accept", accept));
+            }
+            let req = req.to_http_request();
+            let format = ResponseFormat::extract(&req).await.unwrap();
+            assert_eq!(format, expected, "Accept: {:?}", accept);
+        }
+    }
+
+    #[actix_web::test]
+    async fn scopes_can_pin_the_format() {
+        let req = TestRequest::post()
+            .app_data(ResponseFormat::Json)
+            .to_http_request();
+        assert_eq!(
+            ResponseFormat::extract(&req).await.unwrap(),
+            ResponseFormat::Json
+        );
+    }
+}
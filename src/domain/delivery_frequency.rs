@@ -0,0 +1,58 @@
+/// How often a subscriber wants to hear from us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryFrequency {
+    /// Every issue, as soon as it is published.
+    Immediate,
+    /// The issues of the week, bundled into a single email.
+    WeeklyDigest,
+}
+
+impl DeliveryFrequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryFrequency::Immediate => "immediate",
+            DeliveryFrequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+}
+
+impl TryFrom<String> for DeliveryFrequency {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "immediate" => Ok(Self::Immediate),
+            "weekly_digest" => Ok(Self::WeeklyDigest),
+            other => Err(format!(
+                "{} is not a supported delivery frequency. \
+                Use either `immediate` or `weekly_digest`.",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::DeliveryFrequency;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn stored_frequencies_round_trip() {
+        for frequency in [
+            DeliveryFrequency::Immediate,
+            DeliveryFrequency::WeeklyDigest,
+        ] {
+            assert_ok_eq!(
+                DeliveryFrequency::try_from(frequency.as_str().to_string()),
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_frequencies_are_rejected() {
+        assert_err!(DeliveryFrequency::try_from("monthly".to_string()));
+    }
+}
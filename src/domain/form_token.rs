@@ -0,0 +1,107 @@
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// Embedded in the subscription form when it is served, so that we can tell
+/// when it was rendered without having to store anything in the database.
+/// It is bound to the client the form was served to: a token collected once
+/// can't be replayed from elsewhere.
+#[derive(Debug)]
+pub struct FormToken(String);
+
+impl FormToken {
+    pub fn generate(
+        issued_at: DateTime<Utc>,
+        client_ip: &str,
+        secret: &Secret<String>,
+    ) -> FormToken {
+        let issued_at = issued_at.timestamp();
+        let signature = hex::encode(sign(issued_at, client_ip, secret).finalize().into_bytes());
+        Self(format!("{}.{}", issued_at, signature))
+    }
+
+    /// Return when the token was issued, if the signature checks out for `client_ip`.
+    pub fn verify(
+        s: &str,
+        client_ip: &str,
+        secret: &Secret<String>,
+    ) -> Result<DateTime<Utc>, String> {
+        let invalid_token = || format!("{} is not a valid form token.", s);
+        let (issued_at, signature) = s.split_once('.').ok_or_else(invalid_token)?;
+        let issued_at: i64 = issued_at.parse().map_err(|_| invalid_token())?;
+        let signature = hex::decode(signature).map_err(|_| invalid_token())?;
+        sign(issued_at, client_ip, secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid_token())?;
+        Utc.timestamp_opt(issued_at, 0)
+            .single()
+            .ok_or_else(invalid_token)
+    }
+}
+
+fn sign(issued_at: i64, client_ip: &str, secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes()).unwrap();
+    mac.update(b"subscription-form");
+    mac.update(&issued_at.to_be_bytes());
+    mac.update(client_ip.as_bytes());
+    mac
+}
+
+impl AsRef<str> for FormToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormToken;
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+
+    const CLIENT_IP: &str = "203.0.113.1";
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-long-and-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_verified_successfully() {
+        let issued_at = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let token = FormToken::generate(issued_at, CLIENT_IP, &secret());
+        assert_ok_eq!(
+            FormToken::verify(token.as_ref(), CLIENT_IP, &secret()),
+            issued_at
+        );
+    }
+
+    #[test]
+    fn a_token_with_a_tampered_timestamp_is_rejected() {
+        let token = FormToken::generate(Utc::now(), CLIENT_IP, &secret());
+        let (_, signature) = token.as_ref().split_once('.').unwrap();
+        let backdated = format!("{}.{}", 1_600_000_000, signature);
+        assert_err!(FormToken::verify(&backdated, CLIENT_IP, &secret()));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = FormToken::generate(Utc::now(), CLIENT_IP, &secret());
+        let other_secret = Secret::new("another-secret".to_string());
+        assert_err!(FormToken::verify(token.as_ref(), CLIENT_IP, &other_secret));
+    }
+
+    #[test]
+    fn a_token_submitted_from_another_client_is_rejected() {
+        let token = FormToken::generate(Utc::now(), CLIENT_IP, &secret());
+        assert_err!(FormToken::verify(token.as_ref(), "198.51.100.7", &secret()));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "not-a-token", "not-a-number.abcd", "."] {
+            assert_err!(FormToken::verify(token, CLIENT_IP, &secret()));
+        }
+    }
+}
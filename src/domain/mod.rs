@@ -1,7 +1,15 @@
-mod subscriber_name;
+mod delivery_frequency;
+mod form_token;
 mod new_subscriber;
+mod slug;
 mod subscriber_email;
+mod subscriber_name;
+mod unsubscribe_token;
 
-pub use subscriber_name::SubscriberName;
-pub use subscriber_email::SubscriberEmail;
+pub use delivery_frequency::DeliveryFrequency;
+pub use form_token::FormToken;
 pub use new_subscriber::NewSubscriber;
+pub use slug::Slug;
+pub use subscriber_email::SubscriberEmail;
+pub use subscriber_name::SubscriberName;
+pub use unsubscribe_token::UnsubscribeToken;
@@ -0,0 +1,70 @@
+/// The URL-safe handle of a mailing list or a topic, e.g. `engineering-digest`.
+#[derive(Debug)]
+pub struct Slug(String);
+
+impl Slug {
+    /// The list everybody who subscribed before lists existed was moved to.
+    pub const DEFAULT_LIST: &'static str = "newsletter";
+
+    pub fn parse(s: String) -> Result<Slug, String> {
+        let is_empty = s.is_empty();
+        let is_too_long = s.len() > 64;
+        let has_forbidden_characters = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        let has_dangling_hyphen = s.starts_with('-') || s.ends_with('-');
+        if is_empty || is_too_long || has_forbidden_characters || has_dangling_hyphen {
+            Err(format!(
+                "{} is not a valid slug. Use lowercase letters, digits and hyphens.",
+                s
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+impl AsRef<str> for Slug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Slug;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn lowercase_words_joined_by_hyphens_are_valid() {
+        assert_ok!(Slug::parse("product-updates-2024".to_string()));
+    }
+
+    #[test]
+    fn the_default_list_slug_is_valid() {
+        assert_ok!(Slug::parse(Slug::DEFAULT_LIST.to_string()));
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(Slug::parse("".to_string()));
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_err!(Slug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn uppercase_letters_spaces_and_slashes_are_rejected() {
+        for slug in ["Engineering", "product updates", "a/b"] {
+            assert_err!(Slug::parse(slug.to_string()));
+        }
+    }
+
+    #[test]
+    fn leading_or_trailing_hyphens_are_rejected() {
+        assert_err!(Slug::parse("-digest".to_string()));
+        assert_err!(Slug::parse("digest-".to_string()));
+    }
+}
@@ -0,0 +1,85 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A per-subscriber token, signed with a server-side secret, that grants the right to
+/// unsubscribe, or to manage one's preferences, without having to store anything in the database.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, secret: &Secret<String>) -> UnsubscribeToken {
+        let signature = hex::encode(sign(subscriber_id, secret).finalize().into_bytes());
+        Self(format!("{}.{}", subscriber_id, signature))
+    }
+
+    /// Return the id of the subscriber the token was issued for, if the signature checks out.
+    pub fn verify(s: &str, secret: &Secret<String>) -> Result<Uuid, String> {
+        // The token is a credential: keep it out of error messages, and hence out of logs.
+        let invalid_token = || "Not a valid unsubscribe token.".to_string();
+        let (subscriber_id, signature) = s.split_once('.').ok_or_else(invalid_token)?;
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| invalid_token())?;
+        let signature = hex::decode(signature).map_err(|_| invalid_token())?;
+        sign(subscriber_id, secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid_token())?;
+        Ok(subscriber_id)
+    }
+}
+
+fn sign(subscriber_id: Uuid, secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes()).unwrap();
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-long-and-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_generated_token_is_verified_successfully() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, &secret());
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret()),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        let (_, signature) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4(), signature);
+        assert_err!(UnsubscribeToken::verify(&forged, &secret()));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret());
+        let other_secret = Secret::new("another-secret".to_string());
+        assert_err!(UnsubscribeToken::verify(token.as_ref(), &other_secret));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        for token in ["", "not-a-token", "not-a-uuid.abcd", "."] {
+            assert_err!(UnsubscribeToken::verify(token, &secret()));
+        }
+    }
+}
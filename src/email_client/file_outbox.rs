@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{smtp::mime_message, Email, EmailTransport};
+
+/// Writes every email as an `.eml` file to a local directory instead of
+/// delivering it. Meant for development.
+#[derive(Debug)]
+pub struct FileOutboxTransport {
+    directory: PathBuf,
+    outbox: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileOutboxTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        let outbox = AsyncFileTransport::new(&directory);
+        Self { directory, outbox }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileOutboxTransport {
+    fn provider(&self) -> &'static str {
+        "file_outbox"
+    }
+
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = mime_message(email)?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create the outbox directory.")?;
+        let email_id = self
+            .outbox
+            .send(message)
+            .await
+            .context("Failed to write the email to the outbox.")?;
+        tracing::info!(
+            "Wrote email to {}",
+            self.directory.join(format!("{}.eml", email_id)).display()
+        );
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<(), anyhow::Error> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create the outbox directory.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+
+    use crate::{domain::SubscriberEmail, email_client::EmailClient};
+
+    use super::FileOutboxTransport;
+
+    #[tokio::test]
+    async fn emails_are_written_to_the_outbox_as_eml_files() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            FileOutboxTransport::new(&directory),
+        );
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                SubscriberEmail::parse("ursula@example.com".into()).unwrap(),
+                "Welcome!",
+                "<p>Hi there!</p>",
+                "Hi there!",
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains("Subject: Welcome!"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+mod file_outbox;
+mod postmark;
+mod smtp;
+mod suppression;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+
+pub use file_outbox::FileOutboxTransport;
+pub use postmark::{PostmarkTransport, RetryPolicy};
+pub use smtp::SmtpTransport;
+pub use suppression::SuppressionList;
+
+use anyhow::Context;
+
+use crate::{domain::SubscriberEmail, metrics::METRICS};
+
+/// Delivers a fully assembled email through a specific provider.
+#[async_trait::async_trait]
+pub trait EmailTransport: std::fmt::Debug + Send + Sync {
+    /// Identifies the provider in logs and metrics.
+    fn provider(&self) -> &'static str;
+
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error>;
+
+    /// Deliver several emails, returning one outcome per email, in order.
+    /// Providers without a bulk API fall back to sending them one at a time.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), anyhow::Error>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+
+    /// Check that the provider can be reached and accepts our credentials,
+    /// without sending anything.
+    async fn health_check(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// A provider-agnostic email, ready to be handed over to an `EmailTransport`.
+#[derive(Debug)]
+pub struct Email<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: &'a [EmailHeader<'a>],
+    /// Echoed back by providers that notify us of what became of the email.
+    pub metadata: &'a BTreeMap<String, String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+/// One message of a batch handed to `EmailClient::send_batch`.
+#[derive(Debug)]
+pub struct BatchEmail<'a> {
+    pub recipient: SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub unsubscribe_link: Option<&'a str>,
+}
+
+/// The provider refused the email for good, e.g. because the recipient is inactive:
+/// sending it again would only get the same answer.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub struct PermanentFailure(pub String);
+
+/// A recipient the batch could not be delivered to.
+#[derive(Debug)]
+pub struct FailedDelivery {
+    pub recipient: SubscriberEmail,
+    pub error: anyhow::Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Arc<dyn EmailTransport>,
+    suppression_list: Option<SuppressionList>,
+    metadata: BTreeMap<String, String>,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender,
+            transport: Arc::new(transport),
+            suppression_list: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Silently skip every recipient on the suppression list.
+    pub fn with_suppression_list(mut self, suppression_list: SuppressionList) -> Self {
+        self.suppression_list = Some(suppression_list);
+        self
+    }
+
+    /// The same client, sending on behalf of somebody else.
+    pub fn with_sender(&self, sender: SubscriberEmail) -> Self {
+        Self {
+            sender,
+            ..self.clone()
+        }
+    }
+
+    /// The same client, attaching `key` to every email it sends.
+    pub fn with_metadata(&self, key: &str, value: String) -> Self {
+        let mut client = self.clone();
+        client.metadata.insert(key.to_owned(), value);
+        client
+    }
+
+    /// The same client, handing its emails over to another provider account.
+    pub fn with_transport(&self, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            ..self.clone()
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipent: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send(recipent, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Send a newsletter issue, advertising one-click unsubscription (RFC 8058)
+    /// to the recipient's mailbox provider.
+    pub async fn send_newsletter_email(
+        &self,
+        recipent: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: &str,
+    ) -> Result<(), anyhow::Error> {
+        let list_unsubscribe = format!("<{}>", unsubscribe_link);
+        let headers = list_unsubscribe_headers(&list_unsubscribe);
+        self.send(recipent, subject, html_content, text_content, &headers)
+            .await
+    }
+
+    pub async fn health_check(&self) -> Result<(), anyhow::Error> {
+        self.transport.health_check().await
+    }
+
+    /// Send many emails at once, using the provider's bulk API if it has one.
+    /// Returns the recipients that could not be reached, so that callers can
+    /// retry just those.
+    #[tracing::instrument(name = "Send a batch of emails", skip_all, fields(batch_size = emails.len()))]
+    pub async fn send_batch(&self, emails: Vec<BatchEmail<'_>>) -> Vec<FailedDelivery> {
+        let suppressed = match self.suppressed_recipients(&emails).await {
+            Ok(suppressed) => suppressed,
+            Err(e) => {
+                return emails
+                    .into_iter()
+                    .map(|email| FailedDelivery {
+                        recipient: email.recipient,
+                        error: anyhow::anyhow!("{:#}", e),
+                    })
+                    .collect()
+            }
+        };
+        let (skipped, emails): (Vec<_>, Vec<_>) = emails
+            .into_iter()
+            .partition(|email| suppressed.contains(&email.recipient.as_ref().to_lowercase()));
+        if !skipped.is_empty() {
+            tracing::info!(
+                "Skipping {} recipients on the suppression list.",
+                skipped.len()
+            );
+            self.record_outcome("suppressed", skipped.len() as u64);
+        }
+        let list_unsubscribe: Vec<_> = emails
+            .iter()
+            .map(|email| email.unsubscribe_link.map(|link| format!("<{}>", link)))
+            .collect();
+        let headers: Vec<Vec<_>> = list_unsubscribe
+            .iter()
+            .map(|list_unsubscribe| match list_unsubscribe {
+                Some(list_unsubscribe) => list_unsubscribe_headers(list_unsubscribe).into(),
+                None => vec![],
+            })
+            .collect();
+        let outcomes = {
+            let messages: Vec<_> = emails
+                .iter()
+                .zip(&headers)
+                .map(|(email, headers)| Email {
+                    from: self.sender.as_ref(),
+                    to: email.recipient.as_ref(),
+                    subject: email.subject,
+                    html_body: email.html_content,
+                    text_body: email.text_content,
+                    headers,
+                    metadata: &self.metadata,
+                })
+                .collect();
+            self.transport.send_batch(&messages).await
+        };
+        let n_failed = outcomes.iter().filter(|o| o.is_err()).count() as u64;
+        self.record_outcome("sent", outcomes.len() as u64 - n_failed);
+        self.record_outcome("failed", n_failed);
+        emails
+            .into_iter()
+            .zip(outcomes)
+            .filter_map(|(email, outcome)| {
+                outcome.err().map(|error| FailedDelivery {
+                    recipient: email.recipient,
+                    error,
+                })
+            })
+            .collect()
+    }
+
+    async fn send(
+        &self,
+        recipent: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
+    ) -> Result<(), anyhow::Error> {
+        if let Some(suppression_list) = &self.suppression_list {
+            let suppressed = suppression_list
+                .is_suppressed(recipent.as_ref())
+                .await
+                .context("Failed to check the suppression list.")?;
+            if suppressed {
+                tracing::info!("Skipping a recipient on the suppression list.");
+                self.record_outcome("suppressed", 1);
+                return Ok(());
+            }
+        }
+        let email = Email {
+            from: self.sender.as_ref(),
+            to: recipent.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+            metadata: &self.metadata,
+        };
+        let outcome = self.transport.send(&email).await;
+        self.record_outcome(if outcome.is_ok() { "sent" } else { "failed" }, 1);
+        outcome
+    }
+
+    fn record_outcome(&self, outcome: &str, n: u64) {
+        METRICS
+            .email_sends_total
+            .with_label_values(&[self.transport.provider(), outcome])
+            .inc_by(n);
+    }
+
+    /// The recipients of the batch that are on the suppression list, lowercased.
+    async fn suppressed_recipients(
+        &self,
+        emails: &[BatchEmail<'_>],
+    ) -> Result<HashSet<String>, anyhow::Error> {
+        let suppression_list = match &self.suppression_list {
+            Some(suppression_list) => suppression_list,
+            None => return Ok(HashSet::new()),
+        };
+        let recipients: Vec<&str> = emails.iter().map(|e| e.recipient.as_ref()).collect();
+        suppression_list
+            .suppressed_among(&recipients)
+            .await
+            .context("Failed to check the suppression list.")
+    }
+}
+
+fn list_unsubscribe_headers(list_unsubscribe: &str) -> [EmailHeader<'_>; 2] {
+    [
+        EmailHeader {
+            name: "List-Unsubscribe",
+            value: list_unsubscribe,
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post",
+            value: "List-Unsubscribe=One-Click",
+        },
+    ]
+}
@@ -0,0 +1,848 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Context;
+use rand::Rng;
+use reqwest::{header::HeaderMap, Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailHeader, EmailTransport, PermanentFailure};
+use crate::{metrics::METRICS, telemetry::trace_context_headers};
+
+/// Sends emails through Postmark's JSON API.
+#[derive(Clone, Debug)]
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+            retry_policy,
+        }
+    }
+}
+
+/// How the client reacts to transient failures of the email API:
+/// exponential backoff with full jitter, unless the API asks for a specific
+/// delay via `Retry-After`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub retryable_status_codes: Vec<u16>,
+}
+
+impl RetryPolicy {
+    /// A policy that gives up after the first failure.
+    pub fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            retryable_status_codes: vec![],
+        }
+    }
+
+    /// How long to wait before the next attempt, if any should be made.
+    fn delay_before_retry(&self, attempt: u32, failure: &FailedAttempt) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.is_retryable(&failure.error) {
+            return None;
+        }
+        match failure.retry_after {
+            // Retrying earlier than requested is pointless, waiting longer holds up the caller.
+            Some(retry_after) if retry_after > self.max_delay => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    fn is_retryable(&self, error: &reqwest::Error) -> bool {
+        match error.status() {
+            Some(status) => self.retryable_status_codes.contains(&status.as_u16()),
+            None => error.is_timeout() || error.is_connect(),
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let cap = exponential.min(self.max_delay);
+        cap.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+struct FailedAttempt {
+    error: reqwest::Error,
+    retry_after: Option<Duration>,
+}
+
+/// Parse a `Retry-After` header, expressed either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+/// Postmark refuses batches larger than this.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Error codes Postmark answers with no matter how many times the email is sent:
+/// an invalid email request and an inactive (bounced, complained, unsubscribed) recipient.
+const PERMANENT_ERROR_CODES: [i64; 2] = [300, 406];
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    fn provider(&self) -> &'static str {
+        "postmark"
+    }
+
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let url = format!("{}/email", self.base_url);
+        let response = self
+            .post_with_retries(&url, &SendEmailRequest::from(email))
+            .await
+            .context("Postmark rejected the email.")?;
+        if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+            let result: SendEmailResponse = response
+                .json()
+                .await
+                .context("Failed to parse Postmark's rejection.")?;
+            return result.into_outcome();
+        }
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), anyhow::Error>> {
+        // The bulk endpoint buys nothing for a single email.
+        if let [email] = emails {
+            return vec![self.send(email).await];
+        }
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                Err(e) => outcomes.extend(
+                    chunk
+                        .iter()
+                        .map(|_| Err(anyhow::anyhow!("Postmark rejected the batch: {:#}", e))),
+                ),
+            }
+        }
+        outcomes
+    }
+
+    async fn health_check(&self) -> Result<(), anyhow::Error> {
+        self.http_client
+            .get(format!("{}/server", self.base_url))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .context("Failed to reach Postmark.")?
+            .error_for_status()
+            .context("Postmark rejected our credentials.")?;
+        Ok(())
+    }
+}
+
+impl PostmarkTransport {
+    #[tracing::instrument(name = "Send a batch to Postmark", skip_all, fields(batch_size = chunk.len()))]
+    async fn send_chunk(
+        &self,
+        chunk: &[Email<'_>],
+    ) -> Result<Vec<Result<(), anyhow::Error>>, anyhow::Error> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = chunk.iter().map(SendEmailRequest::from).collect();
+        let response = self.post_with_retries(&url, &request_body).await?;
+        if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+            let result: SendEmailResponse = response
+                .json()
+                .await
+                .context("Failed to parse Postmark's rejection.")?;
+            anyhow::bail!(
+                "Postmark rejected the batch (error code {}): {}",
+                result.error_code,
+                result.message
+            );
+        }
+        let results: Vec<SendEmailResponse> = response
+            .json()
+            .await
+            .context("Failed to parse the batch response.")?;
+        // Postmark reports on each message in the order they were submitted.
+        if results.len() != chunk.len() {
+            anyhow::bail!(
+                "Expected {} results in the batch response, got {}.",
+                chunk.len(),
+                results.len()
+            );
+        }
+        Ok(results
+            .into_iter()
+            .map(SendEmailResponse::into_outcome)
+            .collect())
+    }
+
+    async fn post_with_retries<T: serde::Serialize + ?Sized>(
+        &self,
+        url: &str,
+        request_body: &T,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut attempt = 1;
+        loop {
+            let failure = match self.attempt(url, request_body, attempt).await {
+                Ok(response) => return Ok(response),
+                Err(failure) => failure,
+            };
+            match self.retry_policy.delay_before_retry(attempt, &failure) {
+                Some(delay) => {
+                    tracing::warn!(
+                        error.cause_chain = ?failure.error,
+                        "Email delivery attempt {} failed. Retrying in {:?}.",
+                        attempt,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(failure.error),
+            }
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Email delivery attempt",
+        skip(self, url, request_body),
+        fields(http.status_code = tracing::field::Empty)
+    )]
+    async fn attempt<T: serde::Serialize + ?Sized>(
+        &self,
+        url: &str,
+        request_body: &T,
+        attempt: u32,
+    ) -> Result<reqwest::Response, FailedAttempt> {
+        let response = self
+            .http_client
+            .post(url)
+            .headers(trace_context_headers())
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(request_body)
+            .send()
+            .await
+            .map_err(|error| {
+                record_response("none");
+                FailedAttempt {
+                    error,
+                    retry_after: None,
+                }
+            })?;
+        tracing::Span::current().record("http.status_code", response.status().as_u16());
+        record_response(response.status().as_str());
+        // Postmark explains in the body why it refused the email: let the caller look.
+        if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+            return Ok(response);
+        }
+        let retry_after = match response.status() {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                retry_after(response.headers())
+            }
+            _ => None,
+        };
+        response
+            .error_for_status()
+            .map_err(|error| FailedAttempt { error, retry_after })
+    }
+}
+
+fn record_response(status_code: &str) {
+    METRICS
+        .email_provider_responses_total
+        .with_label_values(&["postmark", status_code])
+        .inc();
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+}
+
+impl<'a> From<&'a Email<'a>> for SendEmailRequest<'a> {
+    fn from(email: &'a Email<'a>) -> Self {
+        Self {
+            from: email.from,
+            to: email.to,
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email.headers,
+            metadata: email.metadata,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    error_code: i64,
+    message: String,
+}
+
+impl SendEmailResponse {
+    fn into_outcome(self) -> Result<(), anyhow::Error> {
+        let message = format!(
+            "Postmark rejected the email (error code {}): {}",
+            self.error_code, self.message
+        );
+        match self.error_code {
+            0 => Ok(()),
+            code if PERMANENT_ERROR_CODES.contains(&code) => Err(PermanentFailure(message).into()),
+            _ => Err(anyhow::anyhow!(message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, header, header_exists, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use crate::domain::SubscriberEmail;
+
+    use crate::email_client::{BatchEmail, EmailClient};
+
+    use super::{PostmarkTransport, RetryPolicy};
+
+    struct SendEmailBodyMatcher;
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Acknowledges every message of a batch, mirroring Postmark's response.
+    struct BatchResponder;
+    impl wiremock::Respond for BatchResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    serde_json::json!({
+                        "ErrorCode": 0,
+                        "Message": "OK",
+                        "To": message["To"],
+                    })
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    struct ListUnsubscribeHeadersMatcher;
+    impl wiremock::Match for ListUnsubscribeHeadersMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let body: serde_json::Value = match serde_json::from_slice(&request.body) {
+                Ok(body) => body,
+                Err(_) => return false,
+            };
+            let headers = serde_json::json!([
+                {
+                    "Name": "List-Unsubscribe",
+                    "Value": "<https://example.com/subscriptions/unsubscribe?token=abc>"
+                },
+                {
+                    "Name": "List-Unsubscribe-Post",
+                    "Value": "List-Unsubscribe=One-Click"
+                }
+            ]);
+            body.get("Headers") == Some(&headers)
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            email(),
+            PostmarkTransport::new(
+                base_url,
+                Secret::new(Faker.fake()),
+                std::time::Duration::from_millis(200),
+                RetryPolicy::no_retries(),
+            ),
+        )
+    }
+
+    fn email_client_with_retries(base_url: String, max_attempts: u32) -> EmailClient {
+        EmailClient::new(
+            email(),
+            PostmarkTransport::new(
+                base_url,
+                Secret::new(Faker.fake()),
+                std::time::Duration::from_millis(200),
+                RetryPolicy {
+                    max_attempts,
+                    base_delay: std::time::Duration::from_millis(10),
+                    max_delay: std::time::Duration::from_secs(2),
+                    retryable_status_codes: vec![429, 500, 502, 503, 504],
+                },
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(wiremock::matchers::method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        // Act
+        let _ = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn send_newsletter_email_sends_list_unsubscribe_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(wiremock::matchers::method("POST"))
+            .and(SendEmailBodyMatcher)
+            .and(ListUnsubscribeHeadersMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        // Act
+        let outcome = email_client
+            .send_newsletter_email(
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+                "https://example.com/subscriptions/unsubscribe?token=abc",
+            )
+            .await;
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_success_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome)
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_transient_server_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_the_maximum_number_of_attempts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_client_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_timeouts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri(), 2);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_honours_retry_after() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri(), 2);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let start = std::time::Instant::now();
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_if_retry_after_exceeds_the_maximum_delay() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[test]
+    fn backoff_never_exceeds_the_maximum_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(500),
+            retryable_status_codes: vec![],
+        };
+        for attempt in 1..10 {
+            assert!(policy.backoff(attempt) <= policy.max_delay);
+        }
+    }
+
+    fn batch(recipients: &[SubscriberEmail]) -> Vec<BatchEmail<'static>> {
+        recipients
+            .iter()
+            .map(|recipient| BatchEmail {
+                recipient: recipient.clone(),
+                subject: "Issue #1",
+                html_content: "<p>Hi there!</p>",
+                text_content: "Hi there!",
+                unsubscribe_link: Some("https://example.com/subscriptions/unsubscribe?token=abc"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_recipients_postmark_rejected() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = [email(), email(), email()];
+
+        Mock::given(path("/email/batch"))
+            .and(wiremock::matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 406, "Message": "Address is inactive." },
+                { "ErrorCode": 0, "Message": "OK" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let failed = email_client.send_batch(batch(&recipients)).await;
+
+        // Assert
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].recipient.as_ref(), recipients[1].as_ref());
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches_into_chunks_of_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<_> = (0..501).map(|_| email()).collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let failed = email_client.send_batch(batch(&recipients)).await;
+
+        // Assert
+        assert!(failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_every_recipient_if_the_request_fails() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients = [email(), email()];
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let failed = email_client.send_batch(batch(&recipients)).await;
+
+        // Assert
+        assert_eq!(failed.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn health_check_queries_the_server_endpoint() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/server"))
+            .and(wiremock::matchers::method("GET"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.health_check().await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn health_check_fails_if_postmark_rejects_the_token() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.health_check().await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use lettre::{
+    message::{
+        header::{HeaderName, HeaderValue},
+        MultiPart,
+    },
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{Email, EmailTransport};
+
+/// Sends emails to an SMTP relay.
+#[derive(Clone, Debug)]
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    /// Without `require_tls` the connection stays in plaintext, which is only
+    /// appropriate for a local relay (e.g. MailHog).
+    pub fn new(
+        host: &str,
+        port: u16,
+        require_tls: bool,
+        credentials: Option<(String, Secret<String>)>,
+        timeout: Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let mut builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        let mailer = builder.port(port).timeout(Some(timeout)).build();
+        Ok(Self { mailer })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    fn provider(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, email: &Email<'_>) -> Result<(), anyhow::Error> {
+        let message = mime_message(email)?;
+        self.mailer
+            .send(message)
+            .await
+            .context("The SMTP relay rejected the email.")?;
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<(), anyhow::Error> {
+        let connected = self
+            .mailer
+            .test_connection()
+            .await
+            .context("Failed to reach the SMTP relay.")?;
+        if !connected {
+            anyhow::bail!("The SMTP relay did not respond to NOOP.");
+        }
+        Ok(())
+    }
+}
+
+/// Assemble a multipart (plain text + HTML) MIME message.
+pub(super) fn mime_message(email: &Email<'_>) -> Result<Message, anyhow::Error> {
+    let mut message = Message::builder()
+        .from(email.from.parse().context("Invalid sender address.")?)
+        .to(email.to.parse().context("Invalid recipient address.")?)
+        .subject(email.subject)
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_body.to_owned(),
+            email.html_body.to_owned(),
+        ))
+        .context("Failed to assemble the email.")?;
+    for header in email.headers {
+        let name = HeaderName::new_from_ascii(header.name.to_owned())
+            .with_context(|| format!("Invalid header name: {}", header.name))?;
+        message
+            .headers_mut()
+            .insert_raw(HeaderValue::new(name, header.value.to_owned()));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_ok;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{Email, EmailClient, EmailHeader},
+    };
+
+    use super::{mime_message, SmtpTransport};
+
+    /// A bare-bones SMTP relay, in the spirit of MailHog: it hands the DATA
+    /// section of the first message it receives back to the test.
+    async fn spawn_smtp_relay() -> (u16, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut tx = Some(tx);
+            let mut data = String::new();
+            let mut in_data = false;
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        if let Some(tx) = tx.take() {
+                            let _ = tx.send(std::mem::take(&mut data));
+                        }
+                        writer.write_all(b"250 OK\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.get(..4).unwrap_or_default() {
+                    "DATA" => {
+                        in_data = true;
+                        b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 OK\r\n",
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        });
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_the_message_to_the_smtp_relay() {
+        // Arrange
+        let (port, received) = spawn_smtp_relay().await;
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            false,
+            None,
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+            transport,
+        );
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                SubscriberEmail::parse("ursula@example.com".into()).unwrap(),
+                "Welcome!",
+                "<p>Hi there!</p>",
+                "Hi there!",
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let data = received.await.unwrap();
+        assert!(data.contains("Subject: Welcome!"));
+        assert!(data.contains("To: ursula@example.com"));
+    }
+
+    #[test]
+    fn mime_message_carries_both_bodies_and_custom_headers() {
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe",
+            value: "<https://example.com/subscriptions/unsubscribe?token=abc>",
+        }];
+        let email = Email {
+            from: "newsletter@example.com",
+            to: "ursula@example.com",
+            subject: "Hello",
+            html_body: "<p>Hi there!</p>",
+            text_body: "Hi there!",
+            headers: &headers,
+            metadata: &Default::default(),
+        };
+
+        let formatted = String::from_utf8(mime_message(&email).unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Subject: Hello"));
+        assert!(formatted.contains(
+            "List-Unsubscribe: <https://example.com/subscriptions/unsubscribe?token=abc>"
+        ));
+        assert!(formatted.contains("Content-Type: text/plain"));
+        assert!(formatted.contains("Content-Type: text/html"));
+    }
+}
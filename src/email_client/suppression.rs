@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Addresses a tenant must stop mailing: they either hard-bounced or reported
+/// them as spam. Populated from the email provider's webhooks.
+#[derive(Clone, Debug)]
+pub struct SuppressionList {
+    pool: PgPool,
+    tenant_id: Uuid,
+}
+
+impl SuppressionList {
+    pub fn new(pool: PgPool, tenant_id: Uuid) -> Self {
+        Self { pool, tenant_id }
+    }
+
+    #[tracing::instrument(name = "Check the suppression list", skip(self))]
+    pub async fn is_suppressed(&self, email: &str) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM suppressed_emails WHERE tenant_id = $1 AND email = lower($2)
+            ) AS "suppressed!"
+            "#,
+            self.tenant_id,
+            email
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.suppressed)
+    }
+
+    /// The subset of `emails` that is suppressed, lowercased.
+    #[tracing::instrument(name = "Check the suppression list", skip_all)]
+    pub async fn suppressed_among(&self, emails: &[&str]) -> Result<HashSet<String>, sqlx::Error> {
+        let emails: Vec<String> = emails.iter().map(|email| email.to_lowercase()).collect();
+        let rows = sqlx::query!(
+            r#"SELECT email FROM suppressed_emails WHERE tenant_id = $1 AND email = ANY($2)"#,
+            self.tenant_id,
+            &emails
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.email).collect())
+    }
+}
@@ -0,0 +1,137 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::ErrorInternalServerError,
+    http::{header::AUTHORIZATION, Method, StatusCode},
+    web, FromRequest, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use super::{save_response, try_processing, IdempotencyKey, IdempotencyScope, NextAction};
+use crate::{
+    authentication::{identify_basic, AuthError},
+    session_state::TypedSession,
+    utils::e500,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Replay the first response for mutating requests carrying an `Idempotency-Key` header.
+///
+/// Server errors and other transient failures (e.g. 429s) are not stored: the claim on
+/// the key is rolled back so that the client can retry and have the request processed again.
+/// Requests with credentials that do not check out are passed through untouched.
+pub async fn idempotency(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if is_safe(req.method()) {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    let idempotency_key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        None => return Ok(next.call(req).await?.map_into_boxed_body()),
+        Some(value) => {
+            let key = value
+                .to_str()
+                .map(ToOwned::to_owned)
+                .map_err(|e| e.to_string());
+            match key.and_then(IdempotencyKey::parse) {
+                Ok(key) => key,
+                Err(err) => {
+                    tracing::warn!("Rejecting request with an invalid idempotency key: {}", err);
+                    let response = HttpResponse::BadRequest().finish();
+                    return Ok(req.into_response(response).map_into_boxed_body());
+                }
+            }
+        }
+    };
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .cloned()
+        .ok_or_else(|| ErrorInternalServerError("Missing database pool."))?;
+    let caller_id = match caller_id(&mut req, &pool).await? {
+        Some(caller_id) => caller_id,
+        None => return Ok(next.call(req).await?.map_into_boxed_body()),
+    };
+    let scope = IdempotencyScope {
+        caller_id,
+        method: req.method().to_string(),
+        path: req.path().to_owned(),
+    };
+    let transaction = match try_processing(&pool, &idempotency_key, &scope)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            return Ok(req.into_response(saved_response).map_into_boxed_body());
+        }
+    };
+    let (request, response) = next.call(req).await?.into_parts();
+    if is_transient(response.status()) {
+        // Dropping the transaction releases the key.
+        return Ok(ServiceResponse::new(request, response).map_into_boxed_body());
+    }
+    let response = save_response(
+        transaction,
+        &idempotency_key,
+        &scope,
+        response.map_into_boxed_body(),
+    )
+    .await?;
+    Ok(ServiceResponse::new(request, response))
+}
+
+/// Failures that a retry of the very same request might not run into.
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error()
+        || matches!(
+            status,
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+        )
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Keys are scoped to the authenticated user, whether they logged in or sent Basic
+/// credentials, so that a caller can never replay a response generated for somebody else.
+/// Anonymous callers only get replays of a request with the very same body.
+///
+/// `None` if the request carries credentials that do not check out.
+async fn caller_id(
+    req: &mut ServiceRequest,
+    pool: &PgPool,
+) -> Result<Option<String>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    if let Some(user_id) = session.get_user_id().map_err(e500)? {
+        return Ok(Some(format!("user:{}", user_id)));
+    }
+    if req.headers().contains_key(AUTHORIZATION) {
+        return match identify_basic(req.request(), pool).await {
+            Ok(user_id) => Ok(Some(format!("user:{}", user_id))),
+            Err(AuthError::InvalidCredentials(_)) => Ok(None),
+            Err(e @ AuthError::UnexpectedError(_)) => Err(e500(e)),
+        };
+    }
+    let body = req.extract::<web::Bytes>().await?;
+    let fingerprint = hex::encode(Sha256::digest(&body));
+    req.set_payload(bytes_to_payload(body));
+    Ok(Some(format!("anonymous:{}", fingerprint)))
+}
+
+/// Put a body that has already been read back into the request, for the handler.
+fn bytes_to_payload(body: web::Bytes) -> Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    Payload::from(payload)
+}
@@ -0,0 +1,7 @@
+mod key;
+mod middleware;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use middleware::{idempotency, IDEMPOTENCY_KEY_HEADER};
+pub use persistence::{save_response, try_processing, IdempotencyScope, NextAction};
@@ -0,0 +1,160 @@
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use super::IdempotencyKey;
+use crate::metrics::begin_transaction;
+
+/// Who sent the request and where: a key only ever replays responses within its scope.
+pub struct IdempotencyScope {
+    pub caller_id: String,
+    pub method: String,
+    pub path: String,
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+/// Claim the idempotency key for the current request.
+///
+/// The returned transaction holds the row lock: concurrent requests with the same key
+/// wait on it and then replay whatever response was saved before the commit.
+#[tracing::instrument(skip_all)]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    scope: &IdempotencyScope,
+) -> Result<NextAction, sqlx::Error> {
+    let mut transaction = begin_transaction(pool).await?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+            INSERT INTO idempotency (
+                caller_id,
+                request_method,
+                request_path,
+                idempotency_key,
+                created_at
+            )
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+        "#,
+        scope.caller_id,
+        scope.method,
+        scope.path,
+        idempotency_key.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, scope)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    scope: &IdempotencyScope,
+) -> Result<Option<HttpResponse>, sqlx::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+            SELECT
+                response_status_code as "response_status_code!",
+                response_header_names as "response_header_names!",
+                response_header_values as "response_header_values!",
+                response_body as "response_body!"
+            FROM idempotency
+            WHERE
+                caller_id = $1 AND
+                request_method = $2 AND
+                request_path = $3 AND
+                idempotency_key = $4 AND
+                response_status_code IS NOT NULL
+        "#,
+        scope.caller_id,
+        scope.method,
+        scope.path,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+    let r = match saved_response {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let status_code = StatusCode::from_u16(r.response_status_code as u16)
+        .map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+    let mut response = HttpResponse::build(status_code);
+    for (name, value) in r
+        .response_header_names
+        .into_iter()
+        .zip(r.response_header_values)
+    {
+        response.append_header((name, value));
+    }
+    Ok(Some(response.body(r.response_body)))
+}
+
+/// Store the response in the row claimed by [`try_processing`] and release the lock.
+///
+/// The body has to be buffered in memory to be persisted, hence the returned response
+/// is a copy of the one passed in.
+#[tracing::instrument(skip_all)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    scope: &IdempotencyScope,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let status_code = response_head.status().as_u16() as i16;
+    let (header_names, header_values): (Vec<String>, Vec<Vec<u8>>) = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str().to_owned(), value.as_bytes().to_owned()))
+        .unzip();
+    sqlx::query!(
+        r#"
+            UPDATE idempotency
+            SET
+                response_status_code = $5,
+                response_header_names = $6,
+                response_header_values = $7,
+                response_body = $8
+            WHERE
+                caller_id = $1 AND
+                request_method = $2 AND
+                request_path = $3 AND
+                idempotency_key = $4
+        "#,
+        scope.caller_id,
+        scope.method,
+        scope.path,
+        idempotency_key.as_ref(),
+        status_code,
+        &header_names,
+        &header_values,
+        body.as_ref()
+    )
+    .execute(&mut transaction)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+    transaction
+        .commit()
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{IssueDeliveryWorkerSettings, Settings},
+    domain::{SubscriberEmail, UnsubscribeToken},
+    email_client::{BatchEmail, PermanentFailure},
+    metrics::begin_transaction,
+    shutdown::Shutdown,
+    startup::get_connection_pool,
+    tenancy::Tenants,
+};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub struct IssueDeliveryWorker {
+    pool: PgPool,
+    tenants: Tenants,
+    settings: IssueDeliveryWorkerSettings,
+    unsubscribe_secret: Secret<String>,
+}
+
+impl IssueDeliveryWorker {
+    pub fn build(configuration: Settings) -> Self {
+        let pool = get_connection_pool(&configuration.database);
+        let email_client = configuration.email_client.clone().client();
+        let tenants = Tenants::new(
+            pool.clone(),
+            configuration.application.base_url,
+            configuration.email_client,
+            email_client,
+        );
+        Self {
+            pool,
+            tenants,
+            settings: configuration.issue_delivery_worker,
+            unsubscribe_secret: configuration.application.unsubscribe_secret,
+        }
+    }
+
+    pub fn settings(&self) -> &IssueDeliveryWorkerSettings {
+        &self.settings
+    }
+
+    /// Deliver queued emails until `shutdown` is triggered.
+    /// A delivery that is already under way is always completed first.
+    pub async fn run_until_stopped(self, shutdown: Shutdown) -> Result<(), std::io::Error> {
+        while !shutdown.is_triggered() {
+            let pause = match self.try_execute_task().await {
+                Ok(ExecutionOutcome::EmptyQueue) => self.settings.idle_poll_interval(),
+                Err(_) => std::time::Duration::from_secs(1),
+                Ok(ExecutionOutcome::TaskCompleted) => continue,
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(pause) => {}
+                _ = shutdown.triggered() => {}
+            }
+        }
+        self.pool.close().await;
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            newsletter_issue_id=tracing::field::Empty,
+            batch_size=tracing::field::Empty
+        ),
+        err
+    )]
+    pub async fn try_execute_task(&self) -> Result<ExecutionOutcome, anyhow::Error> {
+        let (mut transaction, task) = match dequeue_task(&self.pool).await? {
+            Some(task) => task,
+            None => return Ok(ExecutionOutcome::EmptyQueue),
+        };
+        tracing::Span::current().record(
+            "newsletter_issue_id",
+            tracing::field::display(task.newsletter_issue_id),
+        );
+        // Digest subscribers get all the issues of the list that are due in one email,
+        // the other subscribers of the issue get it in a single batch.
+        let recipients: Vec<Vec<Task>> = if task.digest {
+            vec![dequeue_digest_tasks(&mut transaction, &task).await?]
+        } else {
+            let others = dequeue_issue_tasks(
+                &mut transaction,
+                &task,
+                (self.settings.batch_size - 1).max(0),
+            )
+            .await?;
+            std::iter::once(task)
+                .chain(others)
+                .map(|task| vec![task])
+                .collect()
+        };
+        tracing::Span::current().record("batch_size", recipients.len());
+        let issues = load_issues(&mut transaction, recipients.iter().flatten()).await?;
+        // Nothing stays locked while the emails are being sent.
+        lease_tasks(
+            &mut transaction,
+            recipients.iter().flatten(),
+            self.settings.lease(),
+        )
+        .await?;
+        transaction.commit().await?;
+
+        let mut deliveries = Vec::with_capacity(recipients.len());
+        let mut completed = Vec::new();
+        let mut failed = Vec::new();
+        for tasks in recipients {
+            let mut subscriber_id = None;
+            let mut issue_ids = Vec::with_capacity(tasks.len());
+            for task in &tasks {
+                match issues[&task.newsletter_issue_id]
+                    .recipients
+                    .get(&task.subscriber_email)
+                {
+                    Some(id) => {
+                        subscriber_id = Some(*id);
+                        issue_ids.push(task.newsletter_issue_id);
+                    }
+                    None => tracing::info!(
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        "Skipping an issue the subscriber no longer wants to receive."
+                    ),
+                }
+            }
+            let subscriber_id = match subscriber_id {
+                Some(subscriber_id) => subscriber_id,
+                None => {
+                    completed.extend(tasks);
+                    continue;
+                }
+            };
+            let email = match SubscriberEmail::parse(tasks[0].subscriber_email.clone()) {
+                Ok(email) => email,
+                Err(err) => {
+                    tracing::warn!(
+                        error.cause_chain = %err,
+                        "Skipping a confirmed subscriber. \
+                        Their stored contact details are invalid."
+                    );
+                    completed.extend(tasks);
+                    continue;
+                }
+            };
+            deliveries.push(Delivery {
+                tasks,
+                subscriber_id,
+                email,
+                issue_ids,
+            });
+        }
+        if let Some(delivery) = deliveries.first() {
+            // Issues of the same list all belong to the same tenant.
+            let tenant_id = issues[&delivery.issue_ids[0]].issue.tenant_id;
+            let tenant = self.tenants.get(tenant_id).await?;
+            let contents: Vec<_> = deliveries
+                .iter()
+                .map(|delivery| {
+                    let issues: Vec<_> = delivery
+                        .issue_ids
+                        .iter()
+                        .map(|id| &issues[id].issue)
+                        .collect();
+                    let token = UnsubscribeToken::generate(
+                        delivery.subscriber_id,
+                        &self.unsubscribe_secret,
+                    );
+                    let unsubscribe_link = format!(
+                        "{}/subscriptions/unsubscribe?token={}&list={}",
+                        tenant.base_url(),
+                        token.as_ref(),
+                        issues[0].list_slug
+                    );
+                    let preferences_link = format!(
+                        "{}/subscriptions/preferences?token={}",
+                        tenant.base_url(),
+                        token.as_ref()
+                    );
+                    let digest = delivery.tasks[0].digest;
+                    (
+                        EmailContent::new(&issues, digest, &preferences_link),
+                        unsubscribe_link,
+                    )
+                })
+                .collect();
+            let emails = deliveries
+                .iter()
+                .zip(&contents)
+                .map(|(delivery, (content, unsubscribe_link))| BatchEmail {
+                    recipient: delivery.email.clone(),
+                    subject: &content.subject,
+                    html_content: &content.html,
+                    text_content: &content.text,
+                    unsubscribe_link: Some(unsubscribe_link),
+                })
+                .collect();
+            let mut failures: HashMap<String, anyhow::Error> = tenant
+                .email_client()
+                .send_batch(emails)
+                .await
+                .into_iter()
+                .map(|failure| (failure.recipient.as_ref().to_owned(), failure.error))
+                .collect();
+            for delivery in deliveries {
+                let err = match failures.remove(delivery.email.as_ref()) {
+                    Some(err) => err,
+                    None => {
+                        completed.extend(delivery.tasks);
+                        continue;
+                    }
+                };
+                let n_attempts = delivery.tasks[0].n_retries + 1;
+                if err.downcast_ref::<PermanentFailure>().is_some() {
+                    tracing::warn!(
+                        error.cause_chain = ?err,
+                        "The email provider refused to deliver issue to a confirmed subscriber. \
+                        Giving up."
+                    );
+                    completed.extend(delivery.tasks);
+                } else if n_attempts < self.settings.max_attempts {
+                    tracing::warn!(
+                        error.cause_chain = ?err,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Retrying later."
+                    );
+                    failed.extend(delivery.tasks);
+                } else {
+                    tracing::error!(
+                        error.cause_chain = ?err,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Giving up after {} attempts.",
+                        n_attempts
+                    );
+                    completed.extend(delivery.tasks);
+                }
+            }
+        }
+
+        let mut transaction = begin_transaction(&self.pool).await?;
+        for task in &failed {
+            reschedule_task(
+                &mut transaction,
+                task,
+                self.settings.retry_delay(task.n_retries),
+            )
+            .await?;
+        }
+        delete_tasks(&mut transaction, &completed).await?;
+        transaction.commit().await?;
+        Ok(ExecutionOutcome::TaskCompleted)
+    }
+}
+
+/// The email owed to one subscriber, and the tasks it settles.
+struct Delivery {
+    tasks: Vec<Task>,
+    subscriber_id: Uuid,
+    email: SubscriberEmail,
+    issue_ids: Vec<Uuid>,
+}
+
+/// What a subscriber gets: a single issue, or a digest of several.
+struct EmailContent {
+    subject: String,
+    html: String,
+    text: String,
+}
+
+impl EmailContent {
+    fn new(issues: &[&NewsletterIssue], digest: bool, preferences_link: &str) -> Self {
+        let (subject, mut html, mut text) = match issues {
+            [issue] if !digest => (
+                issue.title.clone(),
+                issue.html_content.clone(),
+                issue.text_content.clone(),
+            ),
+            _ => (
+                format!("{}: your weekly digest", issues[0].list_name),
+                issues
+                    .iter()
+                    .map(|issue| {
+                        format!(
+                            "<h1>{}</h1>{}",
+                            htmlescape::encode_minimal(&issue.title),
+                            issue.html_content
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("<hr>"),
+                issues
+                    .iter()
+                    .map(|issue| format!("{}\n\n{}", issue.title, issue.text_content))
+                    .collect::<Vec<_>>()
+                    .join("\n\n---\n\n"),
+            ),
+        };
+        html.push_str(&format!(
+            r#"<p><a href="{}">Manage your preferences</a></p>"#,
+            htmlescape::encode_minimal(preferences_link)
+        ));
+        text.push_str(&format!(
+            "\n\nManage your preferences: {}",
+            preferences_link
+        ));
+        Self {
+            subject,
+            html,
+            text,
+        }
+    }
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    digest: bool,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, sqlx::Error> {
+    let mut transaction = begin_transaction(pool).await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+            SELECT newsletter_issue_id, subscriber_email, n_retries, digest
+            FROM issue_delivery_queue
+            WHERE
+                execute_after <= now() AND
+                (leased_until IS NULL OR leased_until <= now())
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+/// Up to `limit` more due tasks of the issue of `task`, for subscribers who don't
+/// get digests.
+#[tracing::instrument(skip_all)]
+async fn dequeue_issue_tasks(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    limit: i64,
+) -> Result<Vec<Task>, sqlx::Error> {
+    sqlx::query_as!(
+        Task,
+        r#"
+            SELECT newsletter_issue_id, subscriber_email, n_retries, digest
+            FROM issue_delivery_queue
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email <> $2 AND
+                NOT digest AND
+                execute_after <= now() AND
+                (leased_until IS NULL OR leased_until <= now())
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT $3
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        limit,
+    )
+    .fetch_all(transaction)
+    .await
+}
+
+/// The due digest tasks of the subscriber for the list of `task`, oldest issue first,
+/// `task` included.
+#[tracing::instrument(skip_all)]
+async fn dequeue_digest_tasks(
+    transaction: &mut PgTransaction,
+    task: &Task,
+) -> Result<Vec<Task>, sqlx::Error> {
+    sqlx::query_as!(
+        Task,
+        r#"
+            SELECT
+                issue_delivery_queue.newsletter_issue_id,
+                issue_delivery_queue.subscriber_email,
+                issue_delivery_queue.n_retries,
+                issue_delivery_queue.digest
+            FROM issue_delivery_queue
+            JOIN newsletter_issues
+                ON newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id
+            WHERE
+                issue_delivery_queue.subscriber_email = $1 AND
+                issue_delivery_queue.digest AND
+                issue_delivery_queue.execute_after <= now() AND
+                (
+                    issue_delivery_queue.leased_until IS NULL OR
+                    issue_delivery_queue.leased_until <= now()
+                ) AND
+                newsletter_issues.list_id = (
+                    SELECT list_id FROM newsletter_issues WHERE newsletter_issue_id = $2
+                )
+            ORDER BY newsletter_issues.published_at
+            FOR UPDATE OF issue_delivery_queue
+            SKIP LOCKED
+        "#,
+        task.subscriber_email,
+        task.newsletter_issue_id,
+    )
+    .fetch_all(transaction)
+    .await
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: std::time::Duration,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE issue_delivery_queue
+            SET
+                n_retries = n_retries + 1,
+                execute_after = now() + make_interval(secs => $3),
+                leased_until = NULL
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn lease_tasks<'a>(
+    transaction: &mut PgTransaction,
+    tasks: impl Iterator<Item = &'a Task>,
+    lease: std::time::Duration,
+) -> Result<(), sqlx::Error> {
+    let (issue_ids, emails): (Vec<_>, Vec<_>) = tasks
+        .map(|task| (task.newsletter_issue_id, task.subscriber_email.clone()))
+        .unzip();
+    sqlx::query!(
+        r#"
+            UPDATE issue_delivery_queue
+            SET leased_until = now() + make_interval(secs => $3)
+            FROM UNNEST($1::uuid[], $2::text[]) AS tasks(newsletter_issue_id, subscriber_email)
+            WHERE
+                issue_delivery_queue.newsletter_issue_id = tasks.newsletter_issue_id AND
+                issue_delivery_queue.subscriber_email = tasks.subscriber_email
+        "#,
+        &issue_ids,
+        &emails,
+        lease.as_secs_f64(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_tasks(transaction: &mut PgTransaction, tasks: &[Task]) -> Result<(), sqlx::Error> {
+    let (issue_ids, emails): (Vec<_>, Vec<_>) = tasks
+        .iter()
+        .map(|task| (task.newsletter_issue_id, task.subscriber_email.clone()))
+        .unzip();
+    sqlx::query!(
+        r#"
+            DELETE FROM issue_delivery_queue
+            USING UNNEST($1::uuid[], $2::text[]) AS tasks(newsletter_issue_id, subscriber_email)
+            WHERE
+                issue_delivery_queue.newsletter_issue_id = tasks.newsletter_issue_id AND
+                issue_delivery_queue.subscriber_email = tasks.subscriber_email
+        "#,
+        &issue_ids,
+        &emails,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    tenant_id: Uuid,
+    list_slug: String,
+    list_name: String,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+/// An issue to deliver, and who among the subscribers it is queued for should still get it.
+struct IssueRecipients {
+    issue: NewsletterIssue,
+    /// Subscriber ids, by email.
+    recipients: HashMap<String, Uuid>,
+}
+
+/// Load the issues of `tasks` along with their recipients: the subscribers who still are
+/// confirmed members of the list of the issue and did not opt out of its topic since.
+#[tracing::instrument(skip_all)]
+async fn load_issues<'a>(
+    transaction: &mut PgTransaction,
+    tasks: impl Iterator<Item = &'a Task>,
+) -> Result<HashMap<Uuid, IssueRecipients>, sqlx::Error> {
+    let (issue_ids, emails): (Vec<_>, Vec<_>) = tasks
+        .map(|task| (task.newsletter_issue_id, task.subscriber_email.clone()))
+        .unzip();
+    let rows = sqlx::query!(
+        r#"
+            SELECT
+                newsletter_issues.newsletter_issue_id,
+                lists.tenant_id,
+                lists.slug AS list_slug,
+                lists.name AS list_name,
+                newsletter_issues.title,
+                newsletter_issues.text_content,
+                newsletter_issues.html_content,
+                COALESCE(
+                    array_agg(tasks.subscriber_email ORDER BY tasks.subscriber_email)
+                        FILTER (WHERE recipients.id IS NOT NULL),
+                    '{}'
+                ) AS "recipient_emails!",
+                COALESCE(
+                    array_agg(recipients.id ORDER BY tasks.subscriber_email)
+                        FILTER (WHERE recipients.id IS NOT NULL),
+                    '{}'
+                ) AS "recipient_ids!"
+            FROM UNNEST($1::uuid[], $2::text[]) AS tasks(newsletter_issue_id, subscriber_email)
+            JOIN newsletter_issues
+                ON newsletter_issues.newsletter_issue_id = tasks.newsletter_issue_id
+            JOIN lists ON lists.id = newsletter_issues.list_id
+            LEFT JOIN LATERAL (
+                SELECT subscriptions.id
+                FROM subscriptions
+                JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+                WHERE
+                    subscriptions.email = tasks.subscriber_email AND
+                    list_memberships.list_id = newsletter_issues.list_id AND
+                    list_memberships.status = 'confirmed' AND
+                    NOT EXISTS (
+                        SELECT 1 FROM subscriber_topic_opt_outs
+                        WHERE
+                            subscriber_topic_opt_outs.subscriber_id = subscriptions.id AND
+                            subscriber_topic_opt_outs.topic_id = newsletter_issues.topic_id
+                    )
+            ) AS recipients ON true
+            GROUP BY newsletter_issues.newsletter_issue_id, lists.id
+        "#,
+        &issue_ids,
+        &emails,
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let issue = NewsletterIssue {
+                tenant_id: row.tenant_id,
+                list_slug: row.list_slug,
+                list_name: row.list_name,
+                title: row.title,
+                text_content: row.text_content,
+                html_content: row.html_content,
+            };
+            let recipients = row
+                .recipient_emails
+                .into_iter()
+                .zip(row.recipient_ids)
+                .collect();
+            (
+                row.newsletter_issue_id,
+                IssueRecipients { issue, recipients },
+            )
+        })
+        .collect())
+}
@@ -1,6 +1,20 @@
+pub mod authentication;
+pub mod bot_protection;
 pub mod configuration;
+pub mod content_negotiation;
+pub mod domain;
+pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod metrics;
+pub mod problem_details;
+pub mod rate_limiting;
+pub mod retention_worker;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
-pub mod domain;
-pub mod email_client;
+pub mod tenancy;
+pub mod utils;
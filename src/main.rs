@@ -1,17 +1,20 @@
-use std::net::TcpListener;
-
-use sqlx::postgres::PgPoolOptions;
+use opentelemetry::trace::TracerProvider as _;
 use zero2prod::configuration::get_configuration;
-use zero2prod::email_client::EmailClient;
-use zero2prod::startup::{run, Application};
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::startup::Application;
+use zero2prod::telemetry::{build_tracer_provider, get_subscriber, init_subscriber};
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
     let configuration = get_configuration().expect("Failed to read configuration.");
+    let tracer_provider = build_tracer_provider(&configuration.telemetry)
+        .expect("Failed to build the tracer provider.");
+    let tracer = tracer_provider.tracer("zero2prod");
+    opentelemetry::global::set_tracer_provider(tracer_provider);
+    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout, tracer);
+    init_subscriber(subscriber);
     let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
-    Ok(())
+    let outcome = application.run_until_stopped().await;
+    // Export the spans still sitting in the batch processor.
+    opentelemetry::global::shutdown_tracer_provider();
+    outcome
 }
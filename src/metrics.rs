@@ -0,0 +1,153 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+};
+use actix_web_lab::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// Process-wide metrics, exposed in the Prometheus text format on `/metrics`.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests_total: IntCounterVec,
+    pub http_request_duration_seconds: HistogramVec,
+    pub db_pool_connections: IntGaugeVec,
+    pub db_pool_acquire_duration_seconds: Histogram,
+    pub email_sends_total: IntCounterVec,
+    pub email_provider_responses_total: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests served."),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent serving HTTP requests.",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Connections held by the Postgres pool.",
+            ),
+            &["state"],
+        )
+        .unwrap();
+        let db_pool_acquire_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "db_pool_acquire_duration_seconds",
+            "Time spent waiting for a connection from the Postgres pool to begin a transaction.",
+        ))
+        .unwrap();
+        let email_sends_total = IntCounterVec::new(
+            Opts::new("email_sends_total", "Emails handed over to EmailClient."),
+            &["provider", "outcome"],
+        )
+        .unwrap();
+        let email_provider_responses_total = IntCounterVec::new(
+            Opts::new(
+                "email_provider_responses_total",
+                "Responses received from the email provider, per attempt.",
+            ),
+            &["provider", "status_code"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http_request_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_acquire_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(email_sends_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(email_provider_responses_total.clone()))
+            .unwrap();
+        Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            db_pool_connections,
+            db_pool_acquire_duration_seconds,
+            email_sends_total,
+            email_provider_responses_total,
+        }
+    }
+}
+
+/// Count and time every request, labelled by the route pattern rather than
+/// the raw path to keep the number of series bounded.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let timer = std::time::Instant::now();
+    let response = next.call(req).await?;
+    let route = response
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".into());
+    let status = response.status().as_u16().to_string();
+    METRICS
+        .http_requests_total
+        .with_label_values(&[&method, &route, &status])
+        .inc();
+    METRICS
+        .http_request_duration_seconds
+        .with_label_values(&[&method, &route])
+        .observe(timer.elapsed().as_secs_f64());
+    Ok(response)
+}
+
+impl Metrics {
+    /// Render every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// The pool doesn't push its stats anywhere: refresh them right before a scrape.
+    /// Only reads its counters, a scrape never waits for a connection.
+    pub fn record_pool_metrics(&self, pool: &PgPool) {
+        let size = pool.size() as i64;
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["active"])
+            .set(size - idle);
+    }
+}
+
+/// Begin a transaction, timing how long it waited for a connection from the pool.
+pub async fn begin_transaction(
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let timer = METRICS.db_pool_acquire_duration_seconds.start_timer();
+    let transaction = pool.begin().await;
+    timer.observe_duration();
+    transaction
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use actix_web::{error::InternalError, http::StatusCode, HttpRequest, HttpResponse};
+
+/// A machine-readable description of an error, as specified by RFC 7807.
+///
+/// Clients are expected to branch on `type`; `title` and `detail` are meant for humans.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// What is wrong with each invalid field of the request, keyed by field name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, String>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, problem_type: &str) -> Self {
+        Self {
+            problem_type: problem_type.to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: None,
+            errors: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_field_error(mut self, field: &str, error: impl Into<String>) -> Self {
+        self.errors.insert(field.to_string(), error.into());
+        self
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .body(serde_json::to_string(self).expect("Problem details are always serializable."))
+    }
+}
+
+/// The problem details returned for any server-side failure.
+/// The root cause is never disclosed to the client: it only ends up in the logs.
+pub fn internal_server_error() -> ProblemDetails {
+    ProblemDetails::new(StatusCode::INTERNAL_SERVER_ERROR, "about:blank")
+}
+
+/// Error handler for the body and query string extractors, so that malformed requests
+/// are reported with the same format as the errors raised by the handlers.
+pub fn invalid_request_handler<E>(err: E, _req: &HttpRequest) -> actix_web::Error
+where
+    E: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    let response = ProblemDetails::new(StatusCode::BAD_REQUEST, "/problems/invalid-request")
+        .with_title("The request could not be parsed.")
+        .with_detail(err.to_string())
+        .to_response();
+    InternalError::from_response(err, response).into()
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{RateLimitDecision, RateLimitStore, TokenBucket};
+
+/// Past this many buckets, full ones are dropped: they behave like new buckets anyway.
+const MIN_BUCKETS_BEFORE_PRUNING: usize = 10_000;
+
+/// Keeps buckets in the memory of the current process.
+/// Each instance enforces its own limits: with several instances behind a load
+/// balancer, clients effectively get a multiple of the configured limits.
+#[derive(Debug)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    states: HashMap<String, BucketState>,
+    /// Pruning again only once the map doubled keeps its cost constant per request,
+    /// however many buckets are still in use.
+    prune_at: usize,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated_at: Instant,
+    time_to_fill: Duration,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self {
+            buckets: Mutex::new(Buckets {
+                states: HashMap::new(),
+                prune_at: MIN_BUCKETS_BEFORE_PRUNING,
+            }),
+        }
+    }
+}
+
+impl Default for InMemoryRateLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.states.len() >= buckets.prune_at {
+            buckets
+                .states
+                .retain(|_, state| now.duration_since(state.updated_at) < state.time_to_fill);
+            buckets.prune_at = MIN_BUCKETS_BEFORE_PRUNING.max(2 * buckets.states.len());
+        }
+        let state = buckets.states.entry(key.to_owned()).or_insert(BucketState {
+            tokens: bucket.capacity as f64,
+            updated_at: now,
+            time_to_fill: bucket.time_to_fill(),
+        });
+        let (tokens, decision) = bucket.take(state.tokens, now.duration_since(state.updated_at));
+        state.tokens = tokens;
+        state.updated_at = now;
+        Ok(decision)
+    }
+
+    async fn forget(&self, key: &str) -> Result<(), anyhow::Error> {
+        self.buckets.lock().unwrap().states.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claim::assert_ok_eq;
+
+    use crate::rate_limiting::{RateLimitDecision, RateLimitStore, TokenBucket};
+
+    use super::InMemoryRateLimitStore;
+
+    #[tokio::test]
+    async fn buckets_are_independent_from_each_other() {
+        let store = InMemoryRateLimitStore::new();
+        let bucket = TokenBucket {
+            capacity: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+
+        assert_ok_eq!(
+            store.acquire("first", &bucket).await,
+            RateLimitDecision::Allowed
+        );
+        assert!(matches!(
+            store.acquire("first", &bucket).await,
+            Ok(RateLimitDecision::Limited { .. })
+        ));
+        assert_ok_eq!(
+            store.acquire("second", &bucket).await,
+            RateLimitDecision::Allowed
+        );
+    }
+}
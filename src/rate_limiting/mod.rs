@@ -0,0 +1,309 @@
+mod memory;
+mod postgres;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+pub use memory::InMemoryRateLimitStore;
+pub use postgres::PgRateLimitStore;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::{configuration::RateLimitingSettings, problem_details::ProblemDetails};
+
+/// Where token buckets are kept between requests.
+#[async_trait::async_trait]
+pub trait RateLimitStore: std::fmt::Debug + Send + Sync {
+    /// Take a token from the bucket identified by `key`, creating it full if needed.
+    async fn acquire(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+    ) -> Result<RateLimitDecision, anyhow::Error>;
+
+    /// Drop the bucket identified by `key`, if there is one.
+    async fn forget(&self, key: &str) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Allows bursts of up to `capacity` requests, then one request every `refill_interval`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucket {
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+impl TokenBucket {
+    /// Take a token from a bucket holding `tokens`, after topping it up for the
+    /// time `elapsed` since it was last updated. Returns the tokens left.
+    pub fn take(&self, tokens: f64, elapsed: Duration) -> (f64, RateLimitDecision) {
+        let refilled = elapsed.as_secs_f64() / self.refill_interval.as_secs_f64();
+        let available = (tokens + refilled).min(self.capacity as f64);
+        if available >= 1.0 {
+            (available - 1.0, RateLimitDecision::Allowed)
+        } else {
+            let retry_after = self.refill_interval.mul_f64(1.0 - available);
+            (available, RateLimitDecision::Limited { retry_after })
+        }
+    }
+
+    /// How long an untouched bucket takes to fill up completely.
+    pub fn time_to_fill(&self) -> Duration {
+        self.refill_interval.saturating_mul(self.capacity)
+    }
+}
+
+/// Applies the configured limits: per client IP on the routes listed in the
+/// settings, and per recipient on the addresses we send confirmation emails to.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    routes: HashMap<String, TokenBucket>,
+    per_email: TokenBucket,
+    trusted_proxies: usize,
+    email_key_secret: Secret<String>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        store: impl RateLimitStore + 'static,
+        settings: &RateLimitingSettings,
+        email_key_secret: Secret<String>,
+    ) -> Self {
+        Self {
+            store: Arc::new(store),
+            routes: settings
+                .routes
+                .iter()
+                .map(|(route, limit)| (route.clone(), limit.bucket()))
+                .collect(),
+            per_email: settings.per_email.bucket(),
+            trusted_proxies: settings.trusted_proxies,
+            email_key_secret,
+        }
+    }
+
+    /// Requests to routes without a configured limit are always allowed.
+    #[tracing::instrument(name = "Check the rate limit of a route", skip(self))]
+    pub async fn check_route(
+        &self,
+        route: &str,
+        client_ip: &str,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        match self.routes.get(route) {
+            Some(bucket) => {
+                let key = format!("route:{}:{}", route, client_ip);
+                self.store.acquire(&key, bucket).await
+            }
+            None => Ok(RateLimitDecision::Allowed),
+        }
+    }
+
+    /// Limits how often we email the same address, however many clients ask us to.
+    #[tracing::instrument(name = "Check the rate limit of a recipient", skip_all)]
+    pub async fn check_email(&self, email: &str) -> Result<RateLimitDecision, anyhow::Error> {
+        self.store
+            .acquire(&self.email_key(email), &self.per_email)
+            .await
+    }
+
+    /// Called when the address is erased, so that its bucket doesn't outlive it.
+    #[tracing::instrument(name = "Forget the rate limit of a recipient", skip_all)]
+    pub async fn forget_email(&self, email: &str) -> Result<(), anyhow::Error> {
+        self.store.forget(&self.email_key(email)).await
+    }
+
+    /// Keys are signed rather than hashed: without the secret, the address
+    /// can't be recovered by hashing candidates until one matches.
+    fn email_key(&self, email: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.email_key_secret.expose_secret().as_bytes())
+                .unwrap();
+        mac.update(email.to_lowercase().as_bytes());
+        format!("email:{}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// The address the request is attributed to.
+    /// Requests that didn't come through all of our proxies are identified by their peer address.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        if self.trusted_proxies > 0 {
+            if let Some(client_ip) = forwarded_client_ip(req.headers(), self.trusted_proxies) {
+                return Some(client_ip);
+            }
+        }
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    }
+}
+
+/// The address the outermost of our `trusted_proxies` saw the request coming from.
+/// Each proxy appends the address of its peer to `X-Forwarded-For`: the hops to the
+/// left of those appended by our own proxies were set by the client, and can't be trusted.
+fn forwarded_client_ip(headers: &header::HeaderMap, trusted_proxies: usize) -> Option<String> {
+    let hops: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    hops.len()
+        .checked_sub(trusted_proxies)
+        .and_then(|i| hops.get(i))
+        .filter(|hop| !hop.is_empty())
+        .map(|hop| hop.to_string())
+}
+
+const TENANT_PREFIX: &str = "/tenants/{tenant_slug}";
+
+/// Reject requests from clients that exceeded the limit of the route they are calling.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    // Tenants served under a path prefix share the limits of the unprefixed routes.
+    let route = req
+        .match_pattern()
+        .map(|pattern| match pattern.strip_prefix(TENANT_PREFIX) {
+            Some(route) => route.to_string(),
+            None => pattern,
+        });
+    if let (Some(limiter), Some(route)) = (limiter, route) {
+        if let Some(client_ip) = limiter.client_ip(req.request()) {
+            match limiter.check_route(&route, &client_ip).await {
+                Ok(RateLimitDecision::Allowed) => {}
+                Ok(RateLimitDecision::Limited { retry_after }) => {
+                    let response = too_many_requests(retry_after);
+                    return Ok(req.into_response(response).map_into_boxed_body());
+                }
+                // Better to let a few requests through than to fail all of them.
+                Err(e) => tracing::warn!(error.cause_chain = ?e, "Failed to apply the rate limit."),
+            }
+        }
+    }
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// A 429 telling the client when it may try again, rounded up to the second.
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = ProblemDetails::new(StatusCode::TOO_MANY_REQUESTS, "/problems/rate-limited")
+        .with_title("Too many requests.")
+        .with_detail(format!("Try again in {} seconds.", seconds))
+        .to_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, header::HeaderValue::from(seconds));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    use super::{forwarded_client_ip, RateLimitDecision, TokenBucket};
+
+    fn bucket() -> TokenBucket {
+        TokenBucket {
+            capacity: 3,
+            refill_interval: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let bucket = bucket();
+        let mut tokens = bucket.capacity as f64;
+        for _ in 0..3 {
+            let (left, decision) = bucket.take(tokens, Duration::ZERO);
+            assert_eq!(decision, RateLimitDecision::Allowed);
+            tokens = left;
+        }
+        let (_, decision) = bucket.take(tokens, Duration::ZERO);
+        assert_eq!(
+            decision,
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_secs(10)
+            }
+        );
+    }
+
+    #[test]
+    fn an_empty_bucket_refills_over_time() {
+        let bucket = bucket();
+        let (tokens, decision) = bucket.take(0.0, Duration::from_secs(4));
+        assert_eq!(
+            decision,
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_secs(6)
+            }
+        );
+
+        let (tokens, decision) = bucket.take(tokens, Duration::from_secs(6));
+        assert_eq!(decision, RateLimitDecision::Allowed);
+        assert!(tokens.abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_bucket_never_holds_more_than_its_capacity() {
+        let bucket = bucket();
+        let (tokens, _) = bucket.take(0.0, Duration::from_secs(3600));
+        assert!((tokens - 2.0).abs() < 1e-9);
+    }
+
+    fn forwarded_for(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(
+                HeaderName::from_static("x-forwarded-for"),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn the_client_is_the_hop_appended_by_the_outermost_trusted_proxy() {
+        let headers = forwarded_for(&["198.51.100.7, 203.0.113.1"]);
+        assert_eq!(
+            forwarded_client_ip(&headers, 1).as_deref(),
+            Some("203.0.113.1")
+        );
+        assert_eq!(
+            forwarded_client_ip(&headers, 2).as_deref(),
+            Some("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn hops_are_read_across_repeated_headers() {
+        let headers = forwarded_for(&["198.51.100.7", "203.0.113.1, 10.0.0.1"]);
+        assert_eq!(
+            forwarded_client_ip(&headers, 2).as_deref(),
+            Some("203.0.113.1")
+        );
+    }
+
+    #[test]
+    fn requests_with_fewer_hops_than_trusted_proxies_are_not_identified() {
+        assert_eq!(
+            forwarded_client_ip(&forwarded_for(&["203.0.113.1"]), 2),
+            None
+        );
+        assert_eq!(forwarded_client_ip(&HeaderMap::new(), 1), None);
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::{RateLimitDecision, RateLimitStore, TokenBucket};
+use crate::metrics::begin_transaction;
+
+/// Keeps buckets in Postgres, so that every instance enforces the same limits.
+/// Costs a round-trip to the database per rate-limited request.
+#[derive(Debug, Clone)]
+pub struct PgRateLimitStore {
+    pool: PgPool,
+}
+
+impl PgRateLimitStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PgRateLimitStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        let mut transaction = begin_transaction(&self.pool)
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")?;
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+            VALUES ($1, $2, now())
+            ON CONFLICT DO NOTHING
+            "#,
+            key,
+            bucket.capacity as f64
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to create the bucket.")?;
+        // Using the database clock keeps instances with skewed clocks consistent.
+        let state = sqlx::query!(
+            r#"
+            SELECT tokens, GREATEST(EXTRACT(EPOCH FROM now() - updated_at), 0)::DOUBLE PRECISION AS "elapsed!"
+            FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to read the bucket.")?;
+        let elapsed = std::time::Duration::from_secs_f64(state.elapsed);
+        let (tokens, decision) = bucket.take(state.tokens, elapsed);
+        sqlx::query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = now()
+            WHERE key = $1
+            "#,
+            key,
+            tokens
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to update the bucket.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to update the bucket.")?;
+        Ok(decision)
+    }
+
+    async fn forget(&self, key: &str) -> Result<(), anyhow::Error> {
+        sqlx::query!(r#"DELETE FROM rate_limit_buckets WHERE key = $1"#, key)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete the bucket.")?;
+        Ok(())
+    }
+}
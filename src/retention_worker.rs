@@ -0,0 +1,108 @@
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{
+        IdempotencySettings, RateLimitingSettings, Settings, SubscriptionTokenSettings,
+    },
+    shutdown::Shutdown,
+    startup::get_connection_pool,
+};
+
+/// Periodically deletes what we no longer need to keep: subscription and data-request
+/// tokens and saved idempotent responses that outlived their TTL, and rate limit
+/// buckets that filled up.
+pub struct RetentionWorker {
+    pool: PgPool,
+    subscription_tokens: SubscriptionTokenSettings,
+    idempotency: IdempotencySettings,
+    rate_limiting: RateLimitingSettings,
+}
+
+impl RetentionWorker {
+    pub fn build(configuration: Settings) -> Self {
+        Self {
+            pool: get_connection_pool(&configuration.database),
+            subscription_tokens: configuration.subscription_tokens,
+            idempotency: configuration.idempotency,
+            rate_limiting: configuration.application.rate_limiting,
+        }
+    }
+
+    pub async fn run_until_stopped(self, shutdown: Shutdown) -> Result<(), std::io::Error> {
+        while !shutdown.is_triggered() {
+            // Failures are already logged by the spans, we'll try again at the next tick.
+            let _ = self.delete_expired_tokens().await;
+            let _ = self.delete_expired_idempotency_keys().await;
+            let _ = self.delete_full_rate_limit_buckets().await;
+            tokio::select! {
+                _ = tokio::time::sleep(self.subscription_tokens.cleanup_interval()) => {}
+                _ = shutdown.triggered() => {}
+            }
+        }
+        self.pool.close().await;
+        Ok(())
+    }
+
+    /// Returns the number of tokens that were deleted.
+    #[tracing::instrument(skip_all, fields(n_deleted = tracing::field::Empty), err)]
+    pub async fn delete_expired_tokens(&self) -> Result<u64, sqlx::Error> {
+        let n_deleted = sqlx::query!(
+            r#"
+            DELETE FROM subscription_tokens
+            WHERE created_at < now() - make_interval(hours => $1)
+            "#,
+            self.subscription_tokens.ttl_hours as i32
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        let n_deleted = n_deleted
+            + sqlx::query!(
+                r#"
+                DELETE FROM subscriber_data_tokens
+                WHERE created_at < now() - make_interval(mins => $1)
+                "#,
+                self.subscription_tokens.data_request_ttl_minutes as i32
+            )
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        tracing::Span::current().record("n_deleted", n_deleted);
+        Ok(n_deleted)
+    }
+
+    /// Returns the number of saved responses that were deleted.
+    #[tracing::instrument(skip_all, fields(n_deleted = tracing::field::Empty), err)]
+    pub async fn delete_expired_idempotency_keys(&self) -> Result<u64, sqlx::Error> {
+        let n_deleted = sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE created_at < now() - make_interval(hours => $1)
+            "#,
+            self.idempotency.ttl_hours as i32
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        tracing::Span::current().record("n_deleted", n_deleted);
+        Ok(n_deleted)
+    }
+
+    /// A bucket left alone long enough is full, just like a missing one.
+    /// Returns the number of buckets that were deleted.
+    #[tracing::instrument(skip_all, fields(n_deleted = tracing::field::Empty), err)]
+    pub async fn delete_full_rate_limit_buckets(&self) -> Result<u64, sqlx::Error> {
+        let n_deleted = sqlx::query!(
+            r#"
+            DELETE FROM rate_limit_buckets
+            WHERE updated_at < now() - make_interval(secs => $1)
+            "#,
+            self.rate_limiting.longest_time_to_fill().as_secs_f64()
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        tracing::Span::current().record("n_deleted", n_deleted);
+        Ok(n_deleted)
+    }
+}
@@ -0,0 +1,11 @@
+mod dashboard;
+mod logout;
+mod password;
+mod subscribers;
+
+pub use dashboard::admin_dashboard;
+pub use logout::log_out;
+pub use password::{change_password, change_password_form};
+pub use subscribers::{
+    admin_delete_subscriber, admin_get_subscriber, admin_list_subscribers, admin_update_subscriber,
+};
@@ -0,0 +1,713 @@
+use std::collections::BTreeMap;
+
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    rate_limiting::RateLimiter,
+    routes::{erase_subscriber, generate_subscription_token, send_confirmation_email, store_token},
+    tenancy::Tenants,
+    utils::error_chain_fmt,
+};
+
+/// The statuses a subscriber can have on each of their lists.
+const STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+/// The statuses an admin can move a subscriber to: only the subscriber can confirm.
+const UPDATABLE_STATUSES: [&str; 2] = ["pending_confirmation", "unsubscribed"];
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberFilters {
+    tenant: Option<String>,
+    /// Subscribers with at least one membership matching both `list` and `status`.
+    list: Option<String>,
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    /// Matched case-insensitively against the email address and the name.
+    q: Option<String>,
+    limit: Option<i64>,
+    /// The `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    /// `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Subscriber {
+    id: Uuid,
+    tenant: String,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    lists: Vec<Membership>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Membership {
+    list: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    tenant: String,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+/// Where a page starts: right after the last subscriber of the previous one,
+/// in the order they subscribed.
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let cursor = format!(
+            "{} {}",
+            self.subscribed_at
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.id
+        );
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(cursor)
+    }
+
+    fn decode(s: &str) -> Result<Self, String> {
+        let invalid_cursor = || format!("{} is not a valid cursor.", s);
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| invalid_cursor())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid_cursor())?;
+        let (subscribed_at, id) = decoded.split_once(' ').ok_or_else(invalid_cursor)?;
+        Ok(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .map_err(|_| invalid_cursor())?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| invalid_cursor())?,
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    email: Option<String>,
+    /// The new status of the subscriber on some of their lists, by list slug.
+    /// Moving them back to `pending_confirmation` emails them a new confirmation link.
+    #[serde(default)]
+    lists: BTreeMap<String, String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum AdminSubscribersError {
+    #[error("{0}")]
+    InvalidQuery(String),
+    #[error("The subscriber details are not valid.")]
+    InvalidSubscriber(Vec<(String, String)>),
+    #[error("There is no subscriber with this id.")]
+    UnknownSubscriber,
+    #[error("Another subscriber of the tenant already has this email address.")]
+    EmailAlreadyTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminSubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminSubscribersError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminSubscribersError::InvalidQuery(_)
+            | AdminSubscribersError::InvalidSubscriber(_) => StatusCode::BAD_REQUEST,
+            AdminSubscribersError::UnknownSubscriber => StatusCode::NOT_FOUND,
+            AdminSubscribersError::EmailAlreadyTaken => StatusCode::CONFLICT,
+            AdminSubscribersError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminSubscribersError::InvalidQuery(e) => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-subscriber-query")
+                    .with_title("The subscriber query is not valid.")
+                    .with_detail(e)
+            }
+            AdminSubscribersError::InvalidSubscriber(errors) => errors.iter().fold(
+                ProblemDetails::new(self.status_code(), "/problems/invalid-subscriber")
+                    .with_title(&self.to_string())
+                    .with_detail(
+                        errors
+                            .iter()
+                            .map(|(_, error)| error.as_str())
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                |problem, (field, error)| problem.with_field_error(field, error),
+            ),
+            AdminSubscribersError::UnknownSubscriber => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-subscriber")
+                    .with_title("The subscriber does not exist.")
+                    .with_detail(self.to_string())
+            }
+            AdminSubscribersError::EmailAlreadyTaken => {
+                ProblemDetails::new(self.status_code(), "/problems/subscriber-already-exists")
+                    .with_title("The subscriber already exists.")
+                    .with_detail(self.to_string())
+            }
+            AdminSubscribersError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+/// Subscribers of the tenants the user is a member of, in the order they subscribed.
+/// Erased subscribers are left out.
+#[tracing::instrument(name = "List subscribers.", skip_all)]
+pub async fn admin_list_subscribers(
+    filters: web::Query<SubscriberFilters>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminSubscribersError> {
+    let filters = filters.into_inner();
+    if let Some(status) = &filters.status {
+        if !STATUSES.contains(&status.as_str()) {
+            return Err(AdminSubscribersError::InvalidQuery(format!(
+                "{} is not a valid status. Use one of {}.",
+                status,
+                STATUSES.join(", ")
+            )));
+        }
+    }
+    let limit = filters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AdminSubscribersError::InvalidQuery(format!(
+            "The limit must be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let cursor = filters
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(AdminSubscribersError::InvalidQuery)?;
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let tenant_ids = get_member_tenants(&mut transaction, **user_id).await?;
+    // One more than asked for, to know whether there is a next page.
+    let mut rows = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+            SELECT
+                subscriptions.id,
+                tenants.slug AS tenant,
+                subscriptions.email,
+                subscriptions.name,
+                subscriptions.subscribed_at
+            FROM subscriptions
+            JOIN tenants ON tenants.id = subscriptions.tenant_id
+            WHERE
+                subscriptions.erased_at IS NULL AND
+                subscriptions.tenant_id = ANY($10) AND
+                ($1::text IS NULL OR tenants.slug = $1) AND
+                (
+                    ($2::text IS NULL AND $3::text IS NULL) OR
+                    EXISTS (
+                        SELECT 1 FROM list_memberships
+                        JOIN lists ON lists.id = list_memberships.list_id
+                        WHERE
+                            list_memberships.subscriber_id = subscriptions.id AND
+                            ($2::text IS NULL OR lists.slug = $2) AND
+                            ($3::text IS NULL OR list_memberships.status = $3)
+                    )
+                ) AND
+                ($4::timestamptz IS NULL OR subscriptions.subscribed_at >= $4) AND
+                ($5::timestamptz IS NULL OR subscriptions.subscribed_at < $5) AND
+                (
+                    $6::text IS NULL OR
+                    strpos(lower(subscriptions.email), lower($6)) > 0 OR
+                    strpos(lower(subscriptions.name), lower($6)) > 0
+                ) AND
+                (
+                    $7::timestamptz IS NULL OR
+                    (subscriptions.subscribed_at, subscriptions.id) > ($7, $8::uuid)
+                )
+            ORDER BY subscriptions.subscribed_at, subscriptions.id
+            LIMIT $9
+        "#,
+        filters.tenant,
+        filters.list,
+        filters.status,
+        filters.subscribed_after,
+        filters.subscribed_before,
+        filters.q,
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+        &tenant_ids
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to look up the subscribers.")?;
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|row| {
+            Cursor {
+                subscribed_at: row.subscribed_at,
+                id: row.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    let subscribers = with_memberships(&mut transaction, rows).await?;
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[tracing::instrument(name = "Get a subscriber.", skip(pool))]
+pub async fn admin_get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminSubscribersError> {
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let tenant_ids = get_member_tenants(&mut transaction, **user_id).await?;
+    let subscriber = get_subscriber(&mut transaction, &tenant_ids, *subscriber_id)
+        .await?
+        .ok_or(AdminSubscribersError::UnknownSubscriber)?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(name = "Update a subscriber.", skip(update, pool, tenants))]
+pub async fn admin_update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    update: web::Json<SubscriberUpdate>,
+    pool: web::Data<PgPool>,
+    tenants: web::Data<Tenants>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminSubscribersError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let SubscriberUpdate { name, email, lists } = update.into_inner();
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let tenant_ids = get_member_tenants(&mut transaction, **user_id).await?;
+    let current = sqlx::query!(
+        r#"
+            SELECT tenant_id, email FROM subscriptions
+            WHERE id = $1 AND tenant_id = ANY($2) AND erased_at IS NULL
+            FOR UPDATE
+        "#,
+        subscriber_id,
+        &tenant_ids
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the subscriber.")?
+    .ok_or(AdminSubscribersError::UnknownSubscriber)?;
+    let memberships = get_memberships(&mut transaction, &[subscriber_id]).await?;
+
+    let mut errors = vec![];
+    let name = name.and_then(|name| {
+        SubscriberName::parse(name)
+            .map_err(|e| errors.push(("name".to_string(), e)))
+            .ok()
+    });
+    let email = email.and_then(|email| {
+        SubscriberEmail::parse(email)
+            .map_err(|e| errors.push(("email".to_string(), e)))
+            .ok()
+    });
+    for (list, status) in &lists {
+        let field = format!("lists.{}", list);
+        if !memberships.iter().any(|(_, m)| &m.list == list) {
+            errors.push((
+                field,
+                format!("The subscriber is not on the {} list.", list),
+            ));
+        } else if !UPDATABLE_STATUSES.contains(&status.as_str()) {
+            errors.push((
+                field,
+                format!(
+                    "{} is not a status you can set. Use one of {}.",
+                    status,
+                    UPDATABLE_STATUSES.join(", ")
+                ),
+            ));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AdminSubscribersError::InvalidSubscriber(errors));
+    }
+
+    if let Some(name) = &name {
+        sqlx::query!(
+            r#"UPDATE subscriptions SET name = $1 WHERE id = $2"#,
+            name.as_ref(),
+            subscriber_id
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to update the name of the subscriber.")?;
+    }
+    // The names of the lists the subscriber must confirm again, by list id.
+    let mut to_confirm = BTreeMap::new();
+    if let Some(email) = email
+        .as_ref()
+        .filter(|email| email.as_ref() != current.email)
+    {
+        to_confirm = update_email(
+            &mut transaction,
+            current.tenant_id,
+            subscriber_id,
+            &current.email,
+            email.as_ref(),
+        )
+        .await?;
+    }
+    for (list, status) in &lists {
+        let list = sqlx::query!(
+            r#"
+                UPDATE list_memberships SET status = $1
+                FROM lists
+                WHERE
+                    list_memberships.list_id = lists.id AND
+                    list_memberships.subscriber_id = $2 AND
+                    lists.slug = $3
+                RETURNING lists.id, lists.name
+            "#,
+            status,
+            subscriber_id,
+            list
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to update the status of the subscriber.")?;
+        if status == "pending_confirmation" {
+            to_confirm.insert(list.id, list.name);
+        } else {
+            to_confirm.remove(&list.id);
+        }
+    }
+    // The name of each list to confirm, with the token to do it.
+    let mut confirmations = vec![];
+    for (list_id, list_name) in to_confirm {
+        let subscription_token = generate_subscription_token();
+        store_token(
+            &mut transaction,
+            subscriber_id,
+            list_id,
+            &subscription_token,
+        )
+        .await
+        .context("Failed to store the confirmation token of the subscriber.")?;
+        confirmations.push((list_name, subscription_token));
+    }
+    let subscriber = get_subscriber(&mut transaction, &tenant_ids, subscriber_id)
+        .await?
+        .context("The subscriber vanished while being updated.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+    if !confirmations.is_empty() {
+        let tenant = tenants.get(current.tenant_id).await?;
+        for (list_name, subscription_token) in confirmations {
+            let recipient = NewSubscriber {
+                email: SubscriberEmail::parse(subscriber.email.clone())
+                    .map_err(anyhow::Error::msg)?,
+                name: SubscriberName::parse(subscriber.name.clone()).map_err(anyhow::Error::msg)?,
+            };
+            send_confirmation_email(
+                tenant.email_client(),
+                recipient,
+                &list_name,
+                tenant.base_url(),
+                &subscription_token,
+            )
+            .await
+            .context("Failed to send a confirmation email.")?;
+        }
+    }
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Subscribers are erased rather than deleted, like when they ask for it themselves.
+#[tracing::instrument(name = "Delete a subscriber.", skip(pool, rate_limiter))]
+pub async fn admin_delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, AdminSubscribersError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let tenant_ids = get_member_tenants(&mut transaction, **user_id).await?;
+    let tenant_id = sqlx::query!(
+        r#"SELECT tenant_id FROM subscriptions WHERE id = $1 AND tenant_id = ANY($2)"#,
+        subscriber_id,
+        &tenant_ids
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the subscriber.")?
+    .ok_or(AdminSubscribersError::UnknownSubscriber)?
+    .tenant_id;
+    let email = erase_subscriber(&mut transaction, tenant_id, subscriber_id)
+        .await?
+        .ok_or(AdminSubscribersError::UnknownSubscriber)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    rate_limiter
+        .forget_email(&email)
+        .await
+        .context("Failed to forget the rate limit of the erased subscriber.")?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Nobody confirmed the new address: the memberships the subscriber did not leave
+/// go back to pending, and the confirmation links sent to the old one stop working.
+/// Returns the names of the lists they must confirm again, by list id.
+/// The deliveries waiting in the queue follow the subscriber to their new address,
+/// and go out if they confirm it in time.
+#[tracing::instrument(name = "Update the email of a subscriber.", skip(transaction))]
+async fn update_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    subscriber_id: Uuid,
+    current_email: &str,
+    email: &str,
+) -> Result<BTreeMap<Uuid, String>, AdminSubscribersError> {
+    // Checking first would race with another update to the same address.
+    sqlx::query!(
+        r#"UPDATE subscriptions SET email = $1 WHERE id = $2"#,
+        email,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db_error)
+            if db_error.constraint() == Some("subscriptions_tenant_id_email_key") =>
+        {
+            AdminSubscribersError::EmailAlreadyTaken
+        }
+        _ => anyhow::Error::new(e)
+            .context("Failed to update the email of the subscriber.")
+            .into(),
+    })?;
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscription tokens of the subscriber.")?;
+    let lists = sqlx::query!(
+        r#"
+            UPDATE list_memberships SET status = 'pending_confirmation'
+            FROM lists
+            WHERE
+                list_memberships.list_id = lists.id AND
+                list_memberships.subscriber_id = $1 AND
+                list_memberships.status != 'unsubscribed'
+            RETURNING lists.id, lists.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to reset the memberships of the subscriber.")?;
+    sqlx::query!(
+        r#"
+            UPDATE issue_delivery_queue SET subscriber_email = $1
+            FROM newsletter_issues, lists
+            WHERE
+                newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id AND
+                lists.id = newsletter_issues.list_id AND
+                issue_delivery_queue.subscriber_email = $2 AND
+                lists.tenant_id = $3
+        "#,
+        email,
+        current_email,
+        tenant_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to move the pending deliveries to the new email.")?;
+    Ok(lists.into_iter().map(|list| (list.id, list.name)).collect())
+}
+
+/// The tenants whose subscribers the user can manage.
+#[tracing::instrument(name = "Look up the tenants of a user.", skip(transaction))]
+async fn get_member_tenants(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<Uuid>, anyhow::Error> {
+    let tenants = sqlx::query!(
+        r#"SELECT tenant_id FROM tenant_members WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_all(transaction)
+    .await
+    .context("Failed to look up the tenants of the user.")?;
+    Ok(tenants.into_iter().map(|t| t.tenant_id).collect())
+}
+
+/// Returns `None` if none of `tenant_ids` has such a subscriber, or if they were erased.
+#[tracing::instrument(name = "Look up a subscriber.", skip(transaction))]
+async fn get_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_ids: &[Uuid],
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+            SELECT
+                subscriptions.id,
+                tenants.slug AS tenant,
+                subscriptions.email,
+                subscriptions.name,
+                subscriptions.subscribed_at
+            FROM subscriptions
+            JOIN tenants ON tenants.id = subscriptions.tenant_id
+            WHERE
+                subscriptions.id = $1 AND
+                subscriptions.tenant_id = ANY($2) AND
+                subscriptions.erased_at IS NULL
+        "#,
+        subscriber_id,
+        tenant_ids
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    Ok(with_memberships(transaction, vec![row]).await?.pop())
+}
+
+async fn with_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    rows: Vec<SubscriberRow>,
+) -> Result<Vec<Subscriber>, anyhow::Error> {
+    let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+    let mut memberships = get_memberships(transaction, &ids).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let (lists, rest) = memberships
+                .drain(..)
+                .partition(|(subscriber_id, _)| *subscriber_id == row.id);
+            memberships = rest;
+            Subscriber {
+                id: row.id,
+                tenant: row.tenant,
+                email: row.email,
+                name: row.name,
+                subscribed_at: row.subscribed_at,
+                lists: lists.into_iter().map(|(_, m)| m).collect(),
+            }
+        })
+        .collect())
+}
+
+/// The memberships of the given subscribers, keyed by subscriber id.
+#[tracing::instrument(name = "Look up the lists of subscribers.", skip_all)]
+async fn get_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+) -> Result<Vec<(Uuid, Membership)>, anyhow::Error> {
+    let memberships = sqlx::query!(
+        r#"
+            SELECT
+                list_memberships.subscriber_id,
+                lists.slug AS list,
+                list_memberships.status,
+                list_memberships.subscribed_at
+            FROM list_memberships
+            JOIN lists ON lists.id = list_memberships.list_id
+            WHERE list_memberships.subscriber_id = ANY($1)
+            ORDER BY lists.slug
+        "#,
+        subscriber_ids
+    )
+    .fetch_all(transaction)
+    .await
+    .context("Failed to look up the lists of the subscribers.")?;
+    Ok(memberships
+        .into_iter()
+        .map(|m| {
+            (
+                m.subscriber_id,
+                Membership {
+                    list: m.list,
+                    status: m.status,
+                    subscribed_at: m.subscribed_at,
+                },
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::Cursor;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.subscribed_at, cursor.subscribed_at);
+        assert_eq!(decoded.id, cursor.id);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in ["", "not base64!", "bm90IGEgY3Vyc29y"] {
+            assert!(Cursor::decode(cursor).is_err(), "Cursor: {:?}", cursor);
+        }
+    }
+}
@@ -1,5 +1,146 @@
-use actix_web::{HttpRequest, HttpResponse, Responder};
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    time::Instant,
+};
 
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use anyhow::Context;
+use sqlx::{migrate::Migrator, PgPool};
+
+use crate::{configuration::HealthSettings, email_client::EmailClient, shutdown::Shutdown};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Kept for clients that still poll the original endpoint.
 pub async fn health_check(_: HttpRequest) -> impl Responder {
     HttpResponse::Ok()
 }
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize)]
+pub struct ComponentHealth {
+    status: HealthStatus,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(serde::Serialize)]
+pub struct HealthReport {
+    status: HealthStatus,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+/// The process is up and serving requests. Says nothing about its dependencies.
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(HealthReport {
+        status: HealthStatus::Up,
+        components: BTreeMap::new(),
+    })
+}
+
+/// The instance can do useful work: it is not shutting down, Postgres answers,
+/// the schema is up to date and, if enabled, the email provider accepts our credentials.
+#[tracing::instrument(name = "Check readiness.", skip_all)]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+    shutdown: web::Data<Shutdown>,
+) -> HttpResponse {
+    if shutdown.is_triggered() {
+        return HttpResponse::ServiceUnavailable().json(HealthReport {
+            status: HealthStatus::Down,
+            components: BTreeMap::new(),
+        });
+    }
+    let timeout = settings.timeout();
+    let (database, migrations) = tokio::join!(
+        check(timeout, ping_database(&pool)),
+        check(timeout, check_migrations(&pool)),
+    );
+    let mut components = BTreeMap::from([("database", database), ("migrations", migrations)]);
+    if settings.probe_email_provider {
+        components.insert(
+            "email_provider",
+            check(timeout, email_client.health_check()).await,
+        );
+    }
+    let status = if components.values().all(|c| c.status == HealthStatus::Up) {
+        HealthStatus::Up
+    } else {
+        HealthStatus::Down
+    };
+    let report = HealthReport { status, components };
+    match status {
+        HealthStatus::Up => HttpResponse::Ok().json(report),
+        HealthStatus::Down => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+async fn check(
+    timeout: std::time::Duration,
+    probe: impl Future<Output = Result<(), anyhow::Error>>,
+) -> ComponentHealth {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(timeout, probe).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    // The endpoint is public: the details only go to the logs.
+    let error = match outcome {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "A readiness check failed.");
+            Some("The check failed.")
+        }
+        Err(_) => {
+            tracing::warn!(
+                "A readiness check timed out after {}ms.",
+                timeout.as_millis()
+            );
+            Some("The check timed out.")
+        }
+    };
+    ComponentHealth {
+        status: match error {
+            None => HealthStatus::Up,
+            Some(_) => HealthStatus::Down,
+        },
+        latency_ms,
+        error,
+    }
+}
+
+async fn ping_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS ping")
+        .fetch_one(pool)
+        .await
+        .context("Failed to reach the database.")?;
+    Ok(())
+}
+
+/// Every migration shipped with this binary must have been applied successfully.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied: HashSet<i64> = sqlx::query!("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(pool)
+        .await
+        .context("Failed to read the applied migrations.")?
+        .into_iter()
+        .map(|r| r.version)
+        .collect();
+    let n_pending = MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .count();
+    if n_pending > 0 {
+        anyhow::bail!("{} migrations have not been applied yet.", n_pending);
+    }
+    Ok(())
+}
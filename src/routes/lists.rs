@@ -0,0 +1,191 @@
+use actix_web::{
+    dev::Payload,
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{authenticate_basic, AuthError},
+    domain::Slug,
+    problem_details::{internal_server_error, ProblemDetails},
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+/// The slug of the list a request is about: the `{list_slug}` path segment,
+/// or the default list for the routes that predate multiple lists.
+#[derive(Debug)]
+pub struct TargetList(pub String);
+
+impl FromRequest for TargetList {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let slug = req
+            .match_info()
+            .get("list_slug")
+            .unwrap_or(Slug::DEFAULT_LIST);
+        std::future::ready(Ok(TargetList(slug.to_string())))
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct MailingList {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(name = "Look up a mailing list.", skip(transaction))]
+pub async fn get_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT id, slug, name FROM lists WHERE tenant_id = $1 AND slug = $2"#,
+        tenant_id,
+        slug
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewListData {
+    slug: String,
+    name: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum CreateListError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidList(String),
+    #[error("There already is a list with this slug.")]
+    ListAlreadyExists,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for CreateListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for CreateListError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CreateListError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            CreateListError::InvalidList(_) => StatusCode::BAD_REQUEST,
+            CreateListError::ListAlreadyExists => StatusCode::CONFLICT,
+            CreateListError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            CreateListError::AuthError(_) => {
+                let mut response =
+                    ProblemDetails::new(self.status_code(), "/problems/authentication-failed")
+                        .with_title("Authentication failed.")
+                        .to_response();
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                return response;
+            }
+            CreateListError::InvalidList(e) => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-list")
+                    .with_title("The list details are not valid.")
+                    .with_detail(e)
+            }
+            CreateListError::ListAlreadyExists => {
+                ProblemDetails::new(self.status_code(), "/problems/list-already-exists")
+                    .with_title("The list already exists.")
+                    .with_detail(self.to_string())
+            }
+            CreateListError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "Create a mailing list.",
+    skip(body, tenant, pool, request),
+    fields(
+        list_slug = %body.slug,
+        tenant = %tenant.slug,
+        user_id = tracing::field::Empty
+    )
+)]
+pub async fn create_list(
+    body: web::Json<NewListData>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, CreateListError> {
+    let user_id = authenticate_basic(&request, tenant.id, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => CreateListError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => CreateListError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let NewListData { slug, name } = body.into_inner();
+    let slug = Slug::parse(slug).map_err(CreateListError::InvalidList)?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(CreateListError::InvalidList(
+            "The list name cannot be empty.".into(),
+        ));
+    }
+    let list = insert_list(&pool, tenant.id, &slug, name)
+        .await
+        .context("Failed to insert the new list in the database.")?
+        .ok_or(CreateListError::ListAlreadyExists)?;
+    Ok(HttpResponse::Created().json(list))
+}
+
+/// Returns `None` if the tenant already has a list with this slug.
+#[tracing::instrument(name = "Saving a new list in the database.", skip(pool))]
+async fn insert_list(
+    pool: &PgPool,
+    tenant_id: Uuid,
+    slug: &Slug,
+    name: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    let list = MailingList {
+        id: Uuid::new_v4(),
+        slug: slug.as_ref().to_string(),
+        name: name.to_string(),
+    };
+    let result = sqlx::query!(
+        r#"
+            INSERT INTO lists (id, tenant_id, slug, name, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (tenant_id, slug) DO NOTHING
+        "#,
+        list.id,
+        tenant_id,
+        list.slug,
+        list.name,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok((result.rows_affected() > 0).then_some(list))
+}
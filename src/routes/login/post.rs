@@ -0,0 +1,47 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    session_state::TypedSession,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[tracing::instrument(
+    skip(form, pool, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<LoginFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            // Rotate the session key to prevent session fixation attacks.
+            session.renew();
+            session.insert_user_id(user_id).map_err(e500)?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(err @ AuthError::InvalidCredentials(_)) => {
+            tracing::warn!(error.cause_chain = ?err, "{}", err);
+            FlashMessage::error("Authentication failed.").send();
+            Ok(see_other("/login"))
+        }
+        Err(err @ AuthError::UnexpectedError(_)) => Err(e500(err)),
+    }
+}
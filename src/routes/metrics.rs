@@ -0,0 +1,17 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::{metrics::METRICS, problem_details::internal_server_error};
+
+pub async fn metrics(pool: web::Data<PgPool>) -> HttpResponse {
+    METRICS.record_pool_metrics(&pool);
+    match METRICS.encode() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(prometheus::TEXT_FORMAT)
+            .body(body),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to encode metrics.");
+            internal_server_error().to_response()
+        }
+    }
+}
@@ -1,5 +1,27 @@
+pub mod admin;
 pub mod health_check;
+pub mod lists;
+pub mod login;
+pub mod metrics;
+pub mod newsletters;
 pub mod subscriptions;
+pub mod subscriptions_confirm;
+pub mod subscriptions_me;
+pub mod subscriptions_preferences;
+pub mod subscriptions_unsubscribe;
+pub mod topics;
+pub mod webhooks;
 
+pub use admin::*;
 pub use health_check::*;
+pub use lists::*;
+pub use login::*;
+pub use metrics::*;
+pub use newsletters::*;
 pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriptions_me::*;
+pub use subscriptions_preferences::*;
+pub use subscriptions_unsubscribe::*;
+pub use topics::*;
+pub use webhooks::*;
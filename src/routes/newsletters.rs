@@ -0,0 +1,240 @@
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{authenticate_basic, AuthError},
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    routes::{get_list, get_topic, TargetList},
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    /// The slug of the topic the issue is about, if any: only the subscribers
+    /// interested in it will receive it.
+    #[serde(default)]
+    topic: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("There is no list with this slug.")]
+    UnknownList,
+    #[error("The list has no topic with this slug.")]
+    UnknownTopic,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::UnknownList => StatusCode::NOT_FOUND,
+            PublishError::UnknownTopic => StatusCode::BAD_REQUEST,
+            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::AuthError(_) => {
+                let mut response =
+                    ProblemDetails::new(self.status_code(), "/problems/authentication-failed")
+                        .with_title("Authentication failed.")
+                        .to_response();
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+            PublishError::UnknownList => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-list")
+                    .with_title("The list does not exist.")
+                    .with_detail(self.to_string())
+                    .to_response()
+            }
+            PublishError::UnknownTopic => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-topic")
+                    .with_title("The topic does not exist.")
+                    .with_detail(self.to_string())
+                    .to_response()
+            }
+            PublishError::UnexpectedError(_) => internal_server_error().to_response(),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue.",
+    skip(body, list, tenant, pool, request),
+    fields(
+        newsletter_title = %body.title,
+        list_slug = %list.0,
+        tenant = %tenant.slug,
+        user_id = tracing::field::Empty
+    )
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    list: TargetList,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate_basic(&request, tenant.id, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let list = get_list(&mut transaction, tenant.id, &list.0)
+        .await
+        .context("Failed to look up the list to publish to.")?
+        .ok_or(PublishError::UnknownList)?;
+    let topic_id = match &body.topic {
+        Some(topic) => {
+            let topic = get_topic(&mut transaction, list.id, topic)
+                .await
+                .context("Failed to look up the topic of the issue.")?
+                .ok_or(PublishError::UnknownTopic)?;
+            Some(topic.id)
+        }
+        None => None,
+    };
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        list.id,
+        topic_id,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+    )
+    .await
+    .context("Failed to store newsletter issue details.")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id, list.id, topic_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue.")?;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    topic_id: Option<Uuid>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id,
+                list_id,
+                topic_id,
+                title,
+                text_content,
+                html_content,
+                published_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        newsletter_issue_id,
+        list_id,
+        topic_id,
+        title,
+        text_content,
+        html_content,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+/// Subscribers who opted out of the topic of the issue don't get it. Those who
+/// asked for a weekly digest get it with the next one, on Monday at 8:00 UTC.
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_id: Uuid,
+    topic_id: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO issue_delivery_queue (
+                newsletter_issue_id,
+                subscriber_email,
+                digest,
+                execute_after
+            )
+            SELECT
+                $1,
+                subscriptions.email,
+                COALESCE(subscriber_preferences.delivery_frequency = 'weekly_digest', false),
+                CASE subscriber_preferences.delivery_frequency
+                    WHEN 'weekly_digest' THEN
+                        date_trunc('week', now() - interval '8 hours') + interval '7 days 8 hours'
+                    ELSE now()
+                END
+            FROM subscriptions
+            JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+            LEFT JOIN subscriber_preferences
+                ON subscriber_preferences.subscriber_id = subscriptions.id
+            WHERE
+                list_memberships.list_id = $2 AND
+                list_memberships.status = 'confirmed' AND
+                NOT EXISTS (
+                    SELECT 1 FROM subscriber_topic_opt_outs
+                    WHERE
+                        subscriber_topic_opt_outs.subscriber_id = subscriptions.id AND
+                        subscriber_topic_opt_outs.topic_id = $3
+                )
+        "#,
+        newsletter_issue_id,
+        list_id,
+        topic_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
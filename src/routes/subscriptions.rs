@@ -1,87 +1,445 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
 use chrono::Utc;
-use sqlx::PgPool;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
+    bot_protection::{BotProtection, BotSignals, Verdict},
+    content_negotiation::{is_json, JsonOrForm, ResponseFormat},
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
     email_client::EmailClient,
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    rate_limiting::{too_many_requests, RateLimitDecision, RateLimiter},
+    routes::{get_list, TargetList},
+    tenancy::Tenant,
+    utils::error_chain_fmt,
 };
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    /// Hidden from people by the form, see `BotSignals::honeypot`.
+    #[serde(rename = "website", default)]
+    honeypot: Option<String>,
+    #[serde(default)]
+    form_token: Option<String>,
+    #[serde(default)]
+    captcha_response: Option<String>,
+}
+
+impl FormData {
+    fn bot_signals<'a>(&'a self, client_ip: Option<&'a str>, from_form: bool) -> BotSignals<'a> {
+        BotSignals {
+            honeypot: self.honeypot.as_deref(),
+            form_token: self.form_token.as_deref(),
+            captcha_response: self.captcha_response.as_deref(),
+            client_ip,
+            from_form,
+        }
+    }
+}
+
+/// Marks the route the form of `subscription_form` posts to.
+#[derive(Debug, Clone, Copy)]
+pub struct SubscriptionFormTarget;
+
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    /// Holds the reason each invalid field was rejected.
+    #[error("The subscriber details are not valid.")]
+    InvalidSubscriber {
+        name: Option<String>,
+        email: Option<String>,
+    },
+    #[error("There is no list with this slug.")]
+    UnknownList,
+    #[error("The form has expired.")]
+    ExpiredForm,
+    #[error("The CAPTCHA was not solved.")]
+    CaptchaFailed,
+    #[error("Too many confirmation emails were sent to this address.")]
+    TooManyRequests(std::time::Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribeError::InvalidSubscriber { .. }
+            | SubscribeError::ExpiredForm
+            | SubscribeError::CaptchaFailed => StatusCode::BAD_REQUEST,
+            SubscribeError::UnknownList => StatusCode::NOT_FOUND,
+            SubscribeError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::InvalidSubscriber { name, email } => {
+                let problem = match (name, email) {
+                    (Some(name), None) => {
+                        ProblemDetails::new(self.status_code(), "/problems/invalid-subscriber-name")
+                            .with_title("The subscriber name is not valid.")
+                            .with_detail(name)
+                    }
+                    (None, Some(email)) => ProblemDetails::new(
+                        self.status_code(),
+                        "/problems/invalid-subscriber-email",
+                    )
+                    .with_title("The subscriber email is not valid.")
+                    .with_detail(email),
+                    _ => ProblemDetails::new(self.status_code(), "/problems/invalid-subscriber")
+                        .with_title("The subscriber details are not valid.")
+                        .with_detail("Neither the name nor the email are valid."),
+                };
+                [("name", name), ("email", email)]
+                    .into_iter()
+                    .filter_map(|(field, error)| error.as_ref().map(|e| (field, e)))
+                    .fold(problem, |problem, (field, error)| {
+                        problem.with_field_error(field, error)
+                    })
+            }
+            SubscribeError::UnknownList => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-list")
+                    .with_title("The list does not exist.")
+                    .with_detail(self.to_string())
+            }
+            SubscribeError::ExpiredForm => {
+                ProblemDetails::new(self.status_code(), "/problems/expired-form")
+                    .with_title("The form has expired.")
+                    .with_detail("Reload the page and submit the form again.")
+            }
+            SubscribeError::CaptchaFailed => {
+                ProblemDetails::new(self.status_code(), "/problems/captcha-failed")
+                    .with_title("The CAPTCHA was not solved.")
+            }
+            // Also tells the client when to try again.
+            SubscribeError::TooManyRequests(retry_after) => return too_many_requests(*retry_after),
+            SubscribeError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+/// A minimal subscription form, carrying what `subscribe` needs to spot bots.
+/// It posts to the route it is served under, whichever tenant that belongs to.
+pub async fn subscription_form(
+    request: HttpRequest,
+    rate_limiter: web::Data<RateLimiter>,
+    bot_protection: web::Data<BotProtection>,
+) -> HttpResponse {
+    let action = request.path().trim_end_matches("/new");
+    let client_ip = rate_limiter.client_ip(&request);
+    let form_token = bot_protection.form_token(client_ip.as_deref());
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribe</title>
+</head>
+<body>
+    <form action="{}" method="post">
+        <label>Name
+            <input type="text" placeholder="Enter your name" name="name">
+        </label>
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <div style="position: absolute; left: -10000px;" aria-hidden="true">
+            <label>Leave this field empty
+                <input type="text" name="website" tabindex="-1" autocomplete="off">
+            </label>
+        </div>
+        <input hidden type="text" name="form_token" value="{}">
+        <button type="submit">Subscribe</button>
+    </form>
+</body>
+</html>"#,
+            action,
+            form_token.as_ref()
+        ))
 }
 
 #[tracing::instrument(
     name="Adding a new subscriber.",
-    skip(form, pool),
+    skip(request, form, list, tenant, format, pool, rate_limiter, bot_protection),
     fields(
         subscriber_email=%form.email,
-        subscriber_name=%form.name
+        subscriber_name=%form.name,
+        list_slug=%list.0,
+        tenant=%tenant.slug
         )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    request: HttpRequest,
+    form: JsonOrForm<FormData>,
+    list: TargetList,
+    tenant: Tenant,
+    format: ResponseFormat,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-) -> HttpResponse {
-    let new_subscriber = match form.0.try_into() {
-        Ok(new_subscriber) => new_subscriber,
-        Err(_) => return HttpResponse::BadRequest().finish(),
-    };
-    if insert_subscriber(&pool, &new_subscriber).await.is_err() {
-        return HttpResponse::InternalServerError().finish();
+    rate_limiter: web::Data<RateLimiter>,
+    bot_protection: web::Data<BotProtection>,
+) -> Result<HttpResponse, SubscribeError> {
+    let client_ip = rate_limiter.client_ip(&request);
+    // JSON bodies are sent by API clients, whatever the route.
+    let from_form = request.app_data::<SubscriptionFormTarget>().is_some() && !is_json(&request);
+    match bot_protection
+        .check(&form.bot_signals(client_ip.as_deref(), from_form))
+        .await?
+    {
+        Verdict::Human => {}
+        Verdict::Bot { reason } => {
+            tracing::info!("Discarding a submission from a bot that {}.", reason);
+            return Ok(subscription_response(format, Uuid::new_v4(), &list.0));
+        }
+        Verdict::ExpiredForm => return Err(SubscribeError::ExpiredForm),
+        Verdict::CaptchaFailed => return Err(SubscribeError::CaptchaFailed),
     }
-    if email_client
-        .send_email(
-            new_subscriber.email,
-            "Welcome!",
-            "Welcome to ur newsletter!",
-            "Welcome to ur newsletter!",
-        )
+    let new_subscriber: NewSubscriber = form.into_inner().try_into()?;
+    // Whoever asks, don't flood a mailbox with confirmation emails.
+    if let RateLimitDecision::Limited { retry_after } = rate_limiter
+        .check_email(new_subscriber.email.as_ref())
+        .await
+        .context("Failed to apply the rate limit of the recipient.")?
+    {
+        return Err(SubscribeError::TooManyRequests(retry_after));
+    }
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let list = get_list(&mut transaction, tenant.id, &list.0)
+        .await
+        .context("Failed to look up the list to subscribe to.")?
+        .ok_or(SubscribeError::UnknownList)?;
+    let subscriber_id = upsert_subscriber(&mut transaction, tenant.id, &new_subscriber)
+        .await
+        .context("Failed to store the subscriber in the database.")?;
+    match get_membership_status(&mut transaction, list.id, subscriber_id)
         .await
-        .is_err()
+        .context("Failed to look up the subscriber's membership of the list.")?
+        .as_deref()
     {
-        return HttpResponse::InternalServerError().finish();
+        // Don't reveal whether the address is already on the list.
+        Some("confirmed") => {
+            return Ok(subscription_response(format, subscriber_id, &list.slug));
+        }
+        // They never confirmed, or left: send them a fresh link instead of failing.
+        Some(_) => {}
+        None => insert_membership(&mut transaction, list.id, subscriber_id)
+            .await
+            .context("Failed to add the subscriber to the list.")?,
+    }
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.id,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    send_confirmation_email(
+        tenant.email_client(),
+        new_subscriber,
+        &list.name,
+        tenant.base_url(),
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+    Ok(subscription_response(format, subscriber_id, &list.slug))
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionResponse<'a> {
+    subscriber_id: Uuid,
+    list: &'a str,
+    status: &'static str,
+}
+
+/// The status is `pending_confirmation` even for addresses that are already
+/// confirmed: the response must not reveal who is on the list.
+fn subscription_response(format: ResponseFormat, subscriber_id: Uuid, list: &str) -> HttpResponse {
+    match format {
+        ResponseFormat::Empty => HttpResponse::Ok().finish(),
+        ResponseFormat::Json => HttpResponse::Ok().json(SubscriptionResponse {
+            subscriber_id,
+            list,
+            status: "pending_confirmation",
+        }),
     }
-    HttpResponse::Ok().finish()
 }
 
+#[tracing::instrument(
+    name = "Send a confirmation email to a new subscriber.",
+    skip(email_client, new_subscriber, list_name, base_url, subscription_token)
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    new_subscriber: NewSubscriber,
+    list_name: &str,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let plain_body = format!(
+        "Thanks for subscribing to {}!\nVisit {} to confirm your subscription.",
+        list_name, confirmation_link
+    );
+    let html_body = format!(
+        "Thanks for subscribing to {}!<br />\
+        Click <a href=\"{}\">here</a> to confirm your subscription.",
+        list_name, confirmation_link
+    );
+    email_client
+        .send_email(new_subscriber.email, "Welcome!", &html_body, &plain_body)
+        .await
+}
+
+/// Insert the subscriber, or find the one already using the same email.
+///
+/// Either way the row stays locked until the transaction ends, so that concurrent
+/// requests for the same address are handled one at a time.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database.",
-    skip(new_subscriber, pool)
+    skip(new_subscriber, transaction)
 )]
-pub async fn insert_subscriber(
-    pool: &PgPool,
+pub async fn upsert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
     new_subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<Uuid, sqlx::Error> {
+    let subscriber = sqlx::query!(
         r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'confirmed')
+            INSERT INTO subscriptions (id, tenant_id, email, name, subscribed_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (tenant_id, email) DO UPDATE SET email = EXCLUDED.email
+            RETURNING id
         "#,
         Uuid::new_v4(),
+        tenant_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(pool)
-    .await
-    .map_err(|err| {
-        tracing::error!("Failed to execute query: {:?}", err);
-        err
-    })?;
+    .fetch_one(transaction)
+    .await?;
+    Ok(subscriber.id)
+}
+
+#[tracing::instrument(name = "Looking up a list membership.", skip(transaction))]
+pub async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let membership = sqlx::query!(
+        r#"
+            SELECT status FROM list_memberships
+            WHERE list_id = $1 AND subscriber_id = $2
+            FOR UPDATE
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(membership.map(|m| m.status))
+}
+
+#[tracing::instrument(name = "Adding a subscriber to a list.", skip(transaction))]
+pub async fn insert_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+            VALUES ($1, $2, 'pending_confirmation', $3)
+        "#,
+        list_id,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
     Ok(())
 }
 
+#[tracing::instrument(
+    name = "Store subscription token in the database.",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id, created_at)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        subscription_token,
+        subscriber_id,
+        list_id,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Generate a random 25-characters-long case-sensitive subscription token.
+pub(crate) fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = SubscribeError;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { name, email })
+        match (
+            SubscriberName::parse(value.name),
+            SubscriberEmail::parse(value.email),
+        ) {
+            (Ok(name), Ok(email)) => Ok(Self { name, email }),
+            (name, email) => Err(SubscribeError::InvalidSubscriber {
+                name: name.err(),
+                email: email.err(),
+            }),
+        }
     }
 }
@@ -0,0 +1,151 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::SubscriptionTokenSettings,
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmationError {
+    #[error("There is no subscriber associated with the provided token.")]
+    UnknownToken,
+    #[error("The subscription token has expired. Subscribe again to receive a new one.")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmationError::UnknownToken | ConfirmationError::ExpiredToken => {
+                StatusCode::UNAUTHORIZED
+            }
+            ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmationError::UnknownToken => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-subscription-token")
+                    .with_title("The subscription token is not valid.")
+                    .with_detail(self.to_string())
+            }
+            ConfirmationError::ExpiredToken => {
+                ProblemDetails::new(self.status_code(), "/problems/expired-subscription-token")
+                    .with_title("The subscription token has expired.")
+                    .with_detail(self.to_string())
+            }
+            ConfirmationError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber.",
+    skip(parameters, tenant, pool, token_settings),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    token_settings: web::Data<SubscriptionTokenSettings>,
+) -> Result<HttpResponse, ConfirmationError> {
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let token = take_token(&mut transaction, tenant.id, &parameters.subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber id associated with the provided token.")?
+        .ok_or(ConfirmationError::UnknownToken)?;
+    if token.created_at + token_settings.ttl() < Utc::now() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    confirm_subscriber(&mut transaction, token.subscriber_id, token.list_id)
+        .await
+        .context("Failed to update the subscriber status to `confirmed`.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Confirmation is per list: the token was issued for a single one.
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed.",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE list_memberships SET status = 'confirmed'
+            WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+pub struct StoredToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+/// Tokens are single-use: an old confirmation link must not re-subscribe
+/// somebody who has unsubscribed since.
+#[tracing::instrument(
+    name = "Take subscriber_id from token.",
+    skip(subscription_token, transaction)
+)]
+pub async fn take_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        r#"
+            DELETE FROM subscription_tokens
+            USING subscriptions
+            WHERE
+                subscriptions.id = subscription_tokens.subscriber_id AND
+                subscription_token = $1 AND
+                subscriptions.tenant_id = $2
+            RETURNING subscriber_id, list_id, subscription_tokens.created_at
+        "#,
+        subscription_token,
+        tenant_id,
+    )
+    .fetch_optional(transaction)
+    .await
+}
@@ -0,0 +1,692 @@
+use actix_web::{
+    http::{
+        header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+        StatusCode,
+    },
+    web, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::SubscriptionTokenSettings,
+    content_negotiation::JsonOrForm,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    rate_limiting::{too_many_requests, RateLimitDecision, RateLimiter},
+    routes::subscriptions::generate_subscription_token,
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+/// The only kind of data-subject request a token is good for.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataRequestPurpose {
+    Export,
+    Erasure,
+}
+
+impl DataRequestPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DataRequestPurpose::Export => "export",
+            DataRequestPurpose::Erasure => "erasure",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+    purpose: DataRequestPurpose,
+}
+
+/// Data-subject requests are authenticated with a short-lived token, emailed to the
+/// subscriber when they ask for it. The links in newsletters are not enough: they
+/// never expire and end up in forwarded emails.
+#[derive(serde::Deserialize)]
+pub struct SubscriberDataParameters {
+    token: String,
+}
+
+/// Everything we hold about a subscriber.
+#[derive(Debug, serde::Serialize)]
+pub struct SubscriberData {
+    subscriber: SubscriberRecord,
+    preferences: Option<PreferencesRecord>,
+    lists: Vec<MembershipRecord>,
+    subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pending_deliveries: Vec<PendingDeliveryRecord>,
+    email_events: Vec<EmailEventRecord>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SubscriberRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct PreferencesRecord {
+    delivery_frequency: String,
+    updated_at: DateTime<Utc>,
+    opted_out_topics: Vec<TopicRecord>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct TopicRecord {
+    list: String,
+    topic: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct MembershipRecord {
+    list: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SubscriptionTokenRecord {
+    token: String,
+    list: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct PendingDeliveryRecord {
+    newsletter_issue_id: Uuid,
+    title: String,
+    execute_after: DateTime<Utc>,
+}
+
+/// What the email provider told us about the emails sent to the subscriber.
+#[derive(Debug, serde::Serialize)]
+struct EmailEventRecord {
+    provider: String,
+    record_type: String,
+    payload: serde_json::Value,
+    received_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataError {
+    #[error("{0}")]
+    InvalidEmail(String),
+    #[error("The token is not valid, or it has expired.")]
+    InvalidToken,
+    #[error("Too many emails were sent to this address.")]
+    TooManyRequests(std::time::Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberDataError::InvalidEmail(_) => StatusCode::BAD_REQUEST,
+            SubscriberDataError::InvalidToken => StatusCode::UNAUTHORIZED,
+            SubscriberDataError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            SubscriberDataError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscriberDataError::InvalidEmail(e) => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-subscriber-email")
+                    .with_title("The subscriber email is not valid.")
+                    .with_detail(e)
+                    .with_field_error("email", e)
+            }
+            SubscriberDataError::InvalidToken => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-subscriber-token")
+                    .with_title("The subscriber token is not valid.")
+                    .with_detail(self.to_string())
+            }
+            SubscriberDataError::TooManyRequests(retry_after) => {
+                return too_many_requests(*retry_after)
+            }
+            SubscriberDataError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+/// Email a link to export or erase their data to the subscriber with this address.
+/// The response is the same whether or not the address is subscribed.
+#[tracing::instrument(
+    name = "Request a data-subject link.",
+    skip_all,
+    fields(tenant = %tenant.slug, purpose = ?form.purpose)
+)]
+pub async fn request_subscriber_data(
+    form: JsonOrForm<DataRequestFormData>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    settings: web::Data<SubscriptionTokenSettings>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let DataRequestFormData { email, purpose } = form.into_inner();
+    let email = SubscriberEmail::parse(email).map_err(SubscriberDataError::InvalidEmail)?;
+    if let RateLimitDecision::Limited { retry_after } = rate_limiter
+        .check_email(email.as_ref())
+        .await
+        .context("Failed to apply the rate limit of the recipient.")?
+    {
+        return Err(SubscriberDataError::TooManyRequests(retry_after));
+    }
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber = sqlx::query!(
+        r#"
+            SELECT id FROM subscriptions
+            WHERE tenant_id = $1 AND email = $2 AND erased_at IS NULL
+        "#,
+        tenant.id,
+        email.as_ref()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let subscriber_id = match subscriber {
+        Some(subscriber) => subscriber.id,
+        None => return Ok(HttpResponse::Accepted().finish()),
+    };
+    let token = generate_subscription_token();
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriber_data_tokens (token, subscriber_id, purpose, created_at)
+            VALUES ($1, $2, $3, $4)
+        "#,
+        token,
+        subscriber_id,
+        purpose.as_str(),
+        Utc::now()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the data-request token.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a data-request token.")?;
+    send_data_request_email(
+        tenant.email_client(),
+        email,
+        tenant.base_url(),
+        purpose,
+        &token,
+        settings.data_request_ttl_minutes,
+    )
+    .await
+    .context("Failed to send the data-request email.")?;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[tracing::instrument(
+    name = "Send a data-request email to a subscriber.",
+    skip(email_client, email, base_url, token)
+)]
+async fn send_data_request_email(
+    email_client: &EmailClient,
+    email: SubscriberEmail,
+    base_url: &str,
+    purpose: DataRequestPurpose,
+    token: &str,
+    ttl_minutes: u64,
+) -> Result<(), anyhow::Error> {
+    let (subject, action, link) = match purpose {
+        DataRequestPurpose::Export => (
+            "Your data export",
+            "download a copy of the data we hold about you",
+            format!("{}/subscriptions/me/export?token={}", base_url, token),
+        ),
+        DataRequestPurpose::Erasure => (
+            "Erase your data",
+            "erase the data we hold about you",
+            format!("{}/subscriptions/me/erase?token={}", base_url, token),
+        ),
+    };
+    let plain_body = format!(
+        "Visit {} to {}.\nThe link expires in {} minutes. \
+        If you did not ask for it, you can ignore this email.",
+        link, action, ttl_minutes
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to {}.<br />\
+        The link expires in {} minutes. \
+        If you did not ask for it, you can ignore this email.",
+        link, action, ttl_minutes
+    );
+    email_client
+        .send_email(email, subject, &html_body, &plain_body)
+        .await
+}
+
+#[tracing::instrument(
+    name = "Export the data of a subscriber.",
+    skip_all,
+    fields(tenant = %tenant.slug)
+)]
+pub async fn export_subscriber_data(
+    parameters: web::Query<SubscriberDataParameters>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionTokenSettings>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_id = verify_data_request_token(
+        &mut transaction,
+        tenant.id,
+        &parameters.token,
+        DataRequestPurpose::Export,
+        settings.data_request_ttl(),
+    )
+    .await?
+    .ok_or(SubscriberDataError::InvalidToken)?;
+    let data = get_subscriber_data(&mut transaction, tenant.id, subscriber_id)
+        .await?
+        .ok_or(SubscriberDataError::InvalidToken)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(data))
+}
+
+/// Where the link in the erasure email leads: erasing cannot be undone, so it
+/// takes a second click, which also keeps link scanners from erasing anybody.
+pub async fn erasure_form(
+    parameters: web::Query<SubscriberDataParameters>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    settings: web::Data<SubscriptionTokenSettings>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    verify_data_request_token(
+        &mut transaction,
+        tenant.id,
+        &parameters.token,
+        DataRequestPurpose::Erasure,
+        settings.data_request_ttl(),
+    )
+    .await?
+    .ok_or(SubscriberDataError::InvalidToken)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Erase your data</title>
+</head>
+<body>
+    <p>You will be removed from every list and the data we hold about you will be erased.
+    This cannot be undone.</p>
+    <form action="erase?token={}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&parameters.token)
+        )))
+}
+
+/// Submitted by the form of `erasure_form`.
+pub async fn confirm_erasure(
+    parameters: web::Query<SubscriberDataParameters>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    settings: web::Data<SubscriptionTokenSettings>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    erase(&parameters.token, &tenant, &pool, &rate_limiter, &settings).await?;
+    Ok(HttpResponse::Ok().body("Your data has been erased."))
+}
+
+pub async fn erase_subscriber_data(
+    parameters: web::Query<SubscriberDataParameters>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    settings: web::Data<SubscriptionTokenSettings>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    erase(&parameters.token, &tenant, &pool, &rate_limiter, &settings).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Erase the data of a subscriber.",
+    skip_all,
+    fields(tenant = %tenant.slug)
+)]
+async fn erase(
+    token: &str,
+    tenant: &Tenant,
+    pool: &PgPool,
+    rate_limiter: &RateLimiter,
+    settings: &SubscriptionTokenSettings,
+) -> Result<(), SubscriberDataError> {
+    let mut transaction = begin_transaction(pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscriber_id = verify_data_request_token(
+        &mut transaction,
+        tenant.id,
+        token,
+        DataRequestPurpose::Erasure,
+        settings.data_request_ttl(),
+    )
+    .await?
+    .ok_or(SubscriberDataError::InvalidToken)?;
+    let email = erase_subscriber(&mut transaction, tenant.id, subscriber_id)
+        .await?
+        .ok_or(SubscriberDataError::InvalidToken)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber.")?;
+    rate_limiter
+        .forget_email(&email)
+        .await
+        .context("Failed to forget the rate limit of the erased subscriber.")?;
+    Ok(())
+}
+
+/// Returns the id of the subscriber the token was emailed to, if it was issued
+/// for `purpose`, has not expired and they are still a subscriber of the tenant.
+#[tracing::instrument(name = "Verify a data-request token.", skip(transaction, token))]
+async fn verify_data_request_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    token: &str,
+    purpose: DataRequestPurpose,
+    ttl: chrono::Duration,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+            SELECT subscriptions.id
+            FROM subscriber_data_tokens
+            JOIN subscriptions ON subscriptions.id = subscriber_data_tokens.subscriber_id
+            WHERE
+                subscriber_data_tokens.token = $1 AND
+                subscriber_data_tokens.purpose = $2 AND
+                subscriber_data_tokens.created_at > $3 AND
+                subscriptions.tenant_id = $4 AND
+                subscriptions.erased_at IS NULL
+        "#,
+        token,
+        purpose.as_str(),
+        Utc::now() - ttl,
+        tenant_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to look up the data-request token.")?;
+    Ok(subscriber.map(|s| s.id))
+}
+
+/// Returns `None` if the tenant has no such subscriber, or if they were erased.
+#[tracing::instrument(name = "Get the data of a subscriber.", skip(transaction))]
+pub async fn get_subscriber_data(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberData>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+            SELECT id, email, name, subscribed_at
+            FROM subscriptions
+            WHERE id = $1 AND tenant_id = $2 AND erased_at IS NULL
+        "#,
+        subscriber_id,
+        tenant_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+    let preferences = sqlx::query!(
+        r#"
+            SELECT delivery_frequency, updated_at
+            FROM subscriber_preferences
+            WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the preferences of the subscriber.")?;
+    let preferences = match preferences {
+        Some(preferences) => Some(PreferencesRecord {
+            delivery_frequency: preferences.delivery_frequency,
+            updated_at: preferences.updated_at,
+            opted_out_topics: sqlx::query_as!(
+                TopicRecord,
+                r#"
+                    SELECT lists.slug AS list, topics.slug AS topic
+                    FROM subscriber_topic_opt_outs
+                    JOIN topics ON topics.id = subscriber_topic_opt_outs.topic_id
+                    JOIN lists ON lists.id = topics.list_id
+                    WHERE subscriber_topic_opt_outs.subscriber_id = $1
+                    ORDER BY lists.slug, topics.slug
+                "#,
+                subscriber_id
+            )
+            .fetch_all(&mut *transaction)
+            .await
+            .context("Failed to look up the topics of the subscriber.")?,
+        }),
+        None => None,
+    };
+    let lists = sqlx::query_as!(
+        MembershipRecord,
+        r#"
+            SELECT lists.slug AS list, list_memberships.status, list_memberships.subscribed_at
+            FROM list_memberships
+            JOIN lists ON lists.id = list_memberships.list_id
+            WHERE list_memberships.subscriber_id = $1
+            ORDER BY lists.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the lists of the subscriber.")?;
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+            SELECT
+                subscription_tokens.subscription_token AS token,
+                lists.slug AS list,
+                subscription_tokens.created_at
+            FROM subscription_tokens
+            JOIN lists ON lists.id = subscription_tokens.list_id
+            WHERE subscription_tokens.subscriber_id = $1
+            ORDER BY subscription_tokens.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the subscription tokens of the subscriber.")?;
+    let pending_deliveries = sqlx::query_as!(
+        PendingDeliveryRecord,
+        r#"
+            SELECT
+                issue_delivery_queue.newsletter_issue_id,
+                newsletter_issues.title,
+                issue_delivery_queue.execute_after
+            FROM issue_delivery_queue
+            JOIN newsletter_issues
+                ON newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id
+            JOIN lists ON lists.id = newsletter_issues.list_id
+            WHERE issue_delivery_queue.subscriber_email = $1 AND lists.tenant_id = $2
+            ORDER BY issue_delivery_queue.execute_after
+        "#,
+        subscriber.email,
+        tenant_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the pending deliveries to the subscriber.")?;
+    let email_events = sqlx::query_as!(
+        EmailEventRecord,
+        r#"
+            SELECT provider, record_type, payload, received_at
+            FROM email_events
+            WHERE email = $1 AND (tenant_id = $2 OR tenant_id IS NULL)
+            ORDER BY received_at
+        "#,
+        subscriber.email,
+        tenant_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the email events of the subscriber.")?;
+    Ok(Some(SubscriberData {
+        subscriber,
+        preferences,
+        lists,
+        subscription_tokens,
+        pending_deliveries,
+        email_events,
+    }))
+}
+
+/// Delete everything tied to the subscriber, except their `subscriptions` row and list
+/// memberships, which only keep what the counts of subscriptions over time need.
+/// The suppression list is left alone: an address that bounced or complained must
+/// not be written to again, whoever subscribes it.
+/// Returns the address that was erased, which the caller must also forget the
+/// rate limit of once the transaction is committed, or `None` if the tenant has
+/// no such subscriber, or if they were already erased.
+#[tracing::instrument(name = "Erase a subscriber.", skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+            SELECT email FROM subscriptions
+            WHERE id = $1 AND tenant_id = $2 AND erased_at IS NULL
+            FOR UPDATE
+        "#,
+        subscriber_id,
+        tenant_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let email = match subscriber {
+        Some(subscriber) => subscriber.email,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscription tokens of the subscriber.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscriber_data_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the data-request tokens of the subscriber.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscriber_topic_opt_outs WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the topics of the subscriber.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscriber_preferences WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the preferences of the subscriber.")?;
+    sqlx::query!(
+        r#"
+            DELETE FROM issue_delivery_queue
+            USING newsletter_issues, lists
+            WHERE
+                newsletter_issues.newsletter_issue_id = issue_delivery_queue.newsletter_issue_id AND
+                lists.id = newsletter_issues.list_id AND
+                issue_delivery_queue.subscriber_email = $1 AND
+                lists.tenant_id = $2
+        "#,
+        email,
+        tenant_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the pending deliveries to the subscriber.")?;
+    // Events that could not be attributed to a tenant are erased along with those of the tenant.
+    sqlx::query!(
+        r#"
+            DELETE FROM email_events
+            WHERE email = $1 AND (tenant_id = $2 OR tenant_id IS NULL)
+        "#,
+        email,
+        tenant_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the email events of the subscriber.")?;
+    sqlx::query!(
+        r#"
+            UPDATE list_memberships SET status = 'unsubscribed'
+            WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to unsubscribe the subscriber from their lists.")?;
+    // The address is replaced by one that is unique, as the column requires,
+    // but derived from nothing but the random id of the row.
+    sqlx::query!(
+        r#"
+            UPDATE subscriptions
+            SET email = 'erased-' || id || '@erased.invalid', name = '', erased_at = $2
+            WHERE id = $1
+        "#,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to pseudonymise the subscriber.")?;
+    Ok(Some(email))
+}
@@ -0,0 +1,535 @@
+use std::fmt::Write;
+
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::Utc;
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    content_negotiation::{JsonOrForm, ResponseFormat},
+    domain::{DeliveryFrequency, SubscriberName, UnsubscribeToken},
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    startup::UnsubscribeSecret,
+    tenancy::Tenant,
+    utils::{error_chain_fmt, see_other},
+};
+
+/// The links in the footer of our emails carry the same token as the unsubscribe ones.
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Preferences {
+    name: String,
+    email: String,
+    delivery_frequency: DeliveryFrequency,
+    topics: Vec<TopicPreference>,
+}
+
+/// A topic of one of the lists the subscriber is a confirmed member of.
+#[derive(Debug, serde::Serialize)]
+pub struct TopicPreference {
+    id: Uuid,
+    list: String,
+    name: String,
+    subscribed: bool,
+}
+
+/// Fields that are left out are not changed.
+#[derive(Debug, Default)]
+pub struct PreferencesData {
+    name: Option<String>,
+    delivery_frequency: Option<String>,
+    /// Topic ids: a JSON array, or a form field repeated once per checked box.
+    topics: Option<Vec<String>>,
+}
+
+impl<'de> serde::Deserialize<'de> for PreferencesData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(PreferencesDataVisitor)
+    }
+}
+
+struct PreferencesDataVisitor;
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl<'de> Visitor<'de> for PreferencesDataVisitor {
+    type Value = PreferencesData;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("the preferences of a subscriber")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut data = PreferencesData::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "name" => data.name = Some(map.next_value()?),
+                "delivery_frequency" => data.delivery_frequency = Some(map.next_value()?),
+                "topics" => {
+                    let topics = data.topics.get_or_insert_with(Vec::new);
+                    match map.next_value()? {
+                        OneOrMany::One(topic) => topics.push(topic),
+                        OneOrMany::Many(more) => topics.extend(more),
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        // Forms send an empty `topics` field, so that unchecking every box counts.
+        if let Some(topics) = &mut data.topics {
+            topics.retain(|topic| !topic.is_empty());
+        }
+        Ok(data)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("The subscriber associated with the provided token does not exist.")]
+    UnknownSubscriber,
+    #[error("The preferences are not valid.")]
+    InvalidPreferences(Vec<(&'static str, String)>),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken(_) | PreferencesError::UnknownSubscriber => {
+                StatusCode::UNAUTHORIZED
+            }
+            PreferencesError::InvalidPreferences(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PreferencesError::InvalidToken(_) | PreferencesError::UnknownSubscriber => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-preferences-token")
+                    .with_title("The preferences token is not valid.")
+                    .with_detail(self.to_string())
+            }
+            PreferencesError::InvalidPreferences(errors) => errors.iter().fold(
+                ProblemDetails::new(self.status_code(), "/problems/invalid-preferences")
+                    .with_title(&self.to_string())
+                    .with_detail(
+                        errors
+                            .iter()
+                            .map(|(_, error)| error.as_str())
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                |problem, (field, error)| problem.with_field_error(field, error),
+            ),
+            PreferencesError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "Show the preferences of a subscriber.",
+    skip_all,
+    fields(tenant = %tenant.slug)
+)]
+pub async fn preferences(
+    parameters: web::Query<PreferencesParameters>,
+    tenant: Tenant,
+    format: ResponseFormat,
+    pool: web::Data<PgPool>,
+    secret: web::Data<UnsubscribeSecret>,
+    flash_messages: IncomingFlashMessages,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(PreferencesError::InvalidToken)?;
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let preferences = get_preferences(&mut transaction, tenant.id, subscriber_id)
+        .await?
+        .ok_or(PreferencesError::UnknownSubscriber)?;
+    if format == ResponseFormat::Json {
+        return Ok(HttpResponse::Ok().json(preferences));
+    }
+    let mut messages_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            messages_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(preferences_page(
+            &preferences,
+            &messages_html,
+            &request.uri().to_string(),
+        )))
+}
+
+fn preferences_page(preferences: &Preferences, messages_html: &str, action: &str) -> String {
+    let frequency_options: String = [
+        (
+            DeliveryFrequency::Immediate,
+            "As soon as they are published",
+        ),
+        (DeliveryFrequency::WeeklyDigest, "In a weekly digest"),
+    ]
+    .iter()
+    .map(|(frequency, label)| {
+        format!(
+            r#"<option value="{}"{}>{}</option>"#,
+            frequency.as_str(),
+            if *frequency == preferences.delivery_frequency {
+                " selected"
+            } else {
+                ""
+            },
+            label
+        )
+    })
+    .collect();
+    let topic_checkboxes: String = preferences
+        .topics
+        .iter()
+        .map(|topic| {
+            format!(
+                r#"
+        <label>
+            <input type="checkbox" name="topics" value="{}"{}> {} ({})
+        </label>"#,
+                topic.id,
+                if topic.subscribed { " checked" } else { "" },
+                htmlescape::encode_minimal(&topic.name),
+                htmlescape::encode_minimal(&topic.list)
+            )
+        })
+        .collect();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    {}
+    <p>Preferences of {}</p>
+    <form action="{}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{}">
+        </label>
+        <label>Send me the issues
+            <select name="delivery_frequency">{}</select>
+        </label>
+        <input hidden type="text" name="topics" value="">{}
+        <button type="submit">Save</button>
+    </form>
+</body>
+</html>"#,
+        messages_html,
+        htmlescape::encode_minimal(&preferences.email),
+        htmlescape::encode_minimal(action),
+        htmlescape::encode_minimal(&preferences.name),
+        frequency_options,
+        topic_checkboxes
+    )
+}
+
+#[tracing::instrument(
+    name = "Update the preferences of a subscriber.",
+    skip_all,
+    fields(tenant = %tenant.slug)
+)]
+pub async fn update_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    data: JsonOrForm<PreferencesData>,
+    tenant: Tenant,
+    format: ResponseFormat,
+    pool: web::Data<PgPool>,
+    secret: web::Data<UnsubscribeSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(PreferencesError::InvalidToken)?;
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let current = get_preferences(&mut transaction, tenant.id, subscriber_id)
+        .await?
+        .ok_or(PreferencesError::UnknownSubscriber)?;
+    let PreferencesData {
+        name,
+        delivery_frequency,
+        topics,
+    } = data.into_inner();
+    let mut errors = vec![];
+    let name = name.and_then(|name| {
+        SubscriberName::parse(name)
+            .map_err(|e| errors.push(("name", e)))
+            .ok()
+    });
+    let delivery_frequency = delivery_frequency.and_then(|frequency| {
+        DeliveryFrequency::try_from(frequency)
+            .map_err(|e| errors.push(("delivery_frequency", e)))
+            .ok()
+    });
+    let topic_ids = topics.and_then(|topics| {
+        topics
+            .into_iter()
+            .map(|topic| {
+                Uuid::parse_str(&topic)
+                    .ok()
+                    .filter(|id| current.topics.iter().any(|t| t.id == *id))
+                    .ok_or_else(|| format!("{} is not one of the topics of your lists.", topic))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| errors.push(("topics", e)))
+            .ok()
+    });
+    if !errors.is_empty() {
+        return Err(PreferencesError::InvalidPreferences(errors));
+    }
+    if let Some(name) = &name {
+        update_name(&mut transaction, subscriber_id, name)
+            .await
+            .context("Failed to update the name of the subscriber.")?;
+    }
+    if delivery_frequency.is_some() || topic_ids.is_some() {
+        let delivery_frequency = delivery_frequency.unwrap_or(current.delivery_frequency);
+        store_delivery_frequency(&mut transaction, subscriber_id, delivery_frequency)
+            .await
+            .context("Failed to store the delivery frequency of the subscriber.")?;
+        if let Some(topic_ids) = topic_ids {
+            // Topics that are not listed, among those the subscriber was shown, are opted out of.
+            let (subscribed, opted_out): (Vec<_>, Vec<_>) = current
+                .topics
+                .iter()
+                .map(|t| t.id)
+                .partition(|id| topic_ids.contains(id));
+            store_topic_opt_outs(&mut transaction, subscriber_id, &subscribed, &opted_out)
+                .await
+                .context("Failed to store the topics the subscriber opted out of.")?;
+        }
+        if delivery_frequency == DeliveryFrequency::Immediate {
+            release_digest_deliveries(&mut transaction, tenant.id, &current.email)
+                .await
+                .context("Failed to release the deliveries waiting for the next digest.")?;
+        }
+    }
+    let preferences = get_preferences(&mut transaction, tenant.id, subscriber_id)
+        .await?
+        .context("The subscriber vanished while updating their preferences.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the preferences.")?;
+    match format {
+        ResponseFormat::Json => Ok(HttpResponse::Ok().json(preferences)),
+        ResponseFormat::Empty => {
+            FlashMessage::info("Your preferences have been saved.").send();
+            Ok(see_other(&request.uri().to_string()))
+        }
+    }
+}
+
+/// Returns `None` if the subscriber is not one of the tenant's, or was erased.
+/// Subscribers who never saved their preferences get everything, as soon as it is published.
+#[tracing::instrument(name = "Get the preferences of a subscriber.", skip(transaction))]
+async fn get_preferences(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<Preferences>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+            SELECT
+                subscriptions.name,
+                subscriptions.email,
+                subscriber_preferences.delivery_frequency AS "delivery_frequency?"
+            FROM subscriptions
+            LEFT JOIN subscriber_preferences
+                ON subscriber_preferences.subscriber_id = subscriptions.id
+            WHERE
+                subscriptions.id = $1 AND
+                subscriptions.tenant_id = $2 AND
+                subscriptions.erased_at IS NULL
+        "#,
+        subscriber_id,
+        tenant_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber.")?;
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+    let delivery_frequency = match subscriber.delivery_frequency {
+        Some(frequency) => DeliveryFrequency::try_from(frequency).map_err(anyhow::Error::msg)?,
+        None => DeliveryFrequency::Immediate,
+    };
+    let topics = sqlx::query_as!(
+        TopicPreference,
+        r#"
+            SELECT
+                topics.id,
+                lists.slug AS list,
+                topics.name,
+                NOT EXISTS (
+                    SELECT 1 FROM subscriber_topic_opt_outs
+                    WHERE subscriber_id = $1 AND topic_id = topics.id
+                ) AS "subscribed!"
+            FROM topics
+            JOIN lists ON lists.id = topics.list_id
+            JOIN list_memberships ON list_memberships.list_id = lists.id
+            WHERE
+                list_memberships.subscriber_id = $1 AND
+                list_memberships.status = 'confirmed'
+            ORDER BY lists.slug, topics.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the topics of the subscriber.")?;
+    Ok(Some(Preferences {
+        name: subscriber.name,
+        email: subscriber.email,
+        delivery_frequency,
+        topics,
+    }))
+}
+
+#[tracing::instrument(name = "Update the name of a subscriber.", skip(transaction))]
+async fn update_name(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET name = $1 WHERE id = $2"#,
+        name.as_ref(),
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Store the delivery frequency of a subscriber.",
+    skip(transaction)
+)]
+async fn store_delivery_frequency(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    delivery_frequency: DeliveryFrequency,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriber_preferences (subscriber_id, delivery_frequency, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (subscriber_id) DO UPDATE
+            SET delivery_frequency = EXCLUDED.delivery_frequency, updated_at = EXCLUDED.updated_at
+        "#,
+        subscriber_id,
+        delivery_frequency.as_str(),
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Store the topics a subscriber opted out of.",
+    skip(transaction)
+)]
+async fn store_topic_opt_outs(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscribed: &[Uuid],
+    opted_out: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM subscriber_topic_opt_outs
+            WHERE subscriber_id = $1 AND topic_id = ANY($2)
+        "#,
+        subscriber_id,
+        subscribed
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriber_topic_opt_outs (subscriber_id, topic_id)
+            SELECT $1, topic_id FROM UNNEST($2::uuid[]) AS topic_id
+            ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        opted_out
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// Subscribers going back to immediate delivery get the issues held for their digest right away.
+#[tracing::instrument(name = "Release the deliveries held for a digest.", skip(transaction))]
+async fn release_digest_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Uuid,
+    subscriber_email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE issue_delivery_queue
+            SET digest = false, execute_after = now()
+            FROM newsletter_issues
+            JOIN lists ON lists.id = newsletter_issues.list_id
+            WHERE
+                issue_delivery_queue.newsletter_issue_id = newsletter_issues.newsletter_issue_id AND
+                issue_delivery_queue.subscriber_email = $1 AND
+                issue_delivery_queue.digest AND
+                lists.tenant_id = $2
+        "#,
+        subscriber_email,
+        tenant_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
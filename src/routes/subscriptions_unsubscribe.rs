@@ -0,0 +1,154 @@
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{Slug, UnsubscribeToken},
+    problem_details::{internal_server_error, ProblemDetails},
+    startup::UnsubscribeSecret,
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+    /// Links sent before there were several lists don't name one.
+    #[serde(default = "default_list")]
+    list: String,
+}
+
+fn default_list() -> String {
+    Slug::DEFAULT_LIST.to_string()
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error("The subscriber associated with the provided token is not on this list.")]
+    UnknownSubscriber,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) | UnsubscribeError::UnknownSubscriber => {
+                StatusCode::UNAUTHORIZED
+            }
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            UnsubscribeError::InvalidToken(_) | UnsubscribeError::UnknownSubscriber => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-unsubscribe-token")
+                    .with_title("The unsubscribe token is not valid.")
+            }
+            UnsubscribeError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+/// The link in the email footer: link scanners and prefetchers follow it too, so it
+/// only asks for a confirmation, which is submitted to `unsubscribe`.
+#[tracing::instrument(
+    name = "Show the unsubscription form.",
+    skip(parameters, tenant, secret),
+    fields(list_slug = %parameters.list, tenant = %tenant.slug)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    tenant: Tenant,
+    secret: web::Data<UnsubscribeSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>You will no longer receive the issues of this newsletter.</p>
+    <form action="unsubscribe?token={}&amp;list={}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&parameters.token),
+            htmlescape::encode_minimal(&parameters.list)
+        )))
+}
+
+/// Submitted by the form of `unsubscribe_form`, and the target of the one-click
+/// unsubscription requests sent by mailbox providers (RFC 8058).
+/// The token identifies the subscriber, who is only removed from the list named in the link.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber.",
+    skip(parameters, tenant, pool, secret),
+    fields(list_slug = %parameters.list, tenant = %tenant.slug)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    secret: web::Data<UnsubscribeSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let subscriber_found =
+        unsubscribe_subscriber(&pool, tenant.id, subscriber_id, &parameters.list)
+            .await
+            .context("Failed to update the subscriber status to `unsubscribed`.")?;
+    if !subscriber_found {
+        return Err(UnsubscribeError::UnknownSubscriber);
+    }
+    Ok(HttpResponse::Ok().body("You have been unsubscribed."))
+}
+
+/// Returns `false` if the subscriber is not on the list.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed.", skip(subscriber_id, pool))]
+pub async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    tenant_id: Uuid,
+    subscriber_id: Uuid,
+    list_slug: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+            UPDATE list_memberships SET status = 'unsubscribed'
+            FROM lists
+            WHERE
+                list_memberships.list_id = lists.id AND
+                list_memberships.subscriber_id = $1 AND
+                lists.tenant_id = $2 AND
+                lists.slug = $3
+        "#,
+        subscriber_id,
+        tenant_id,
+        list_slug,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
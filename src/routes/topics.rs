@@ -0,0 +1,195 @@
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{authenticate_basic, AuthError},
+    domain::Slug,
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    routes::{get_list, TargetList},
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+#[derive(Debug, serde::Serialize)]
+pub struct Topic {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(name = "Look up a topic.", skip(transaction))]
+pub async fn get_topic(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    slug: &str,
+) -> Result<Option<Topic>, sqlx::Error> {
+    sqlx::query_as!(
+        Topic,
+        r#"SELECT id, slug, name FROM topics WHERE list_id = $1 AND slug = $2"#,
+        list_id,
+        slug
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewTopicData {
+    slug: String,
+    name: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum CreateTopicError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("There is no list with this slug.")]
+    UnknownList,
+    #[error("{0}")]
+    InvalidTopic(String),
+    #[error("The list already has a topic with this slug.")]
+    TopicAlreadyExists,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for CreateTopicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for CreateTopicError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CreateTopicError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            CreateTopicError::UnknownList => StatusCode::NOT_FOUND,
+            CreateTopicError::InvalidTopic(_) => StatusCode::BAD_REQUEST,
+            CreateTopicError::TopicAlreadyExists => StatusCode::CONFLICT,
+            CreateTopicError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            CreateTopicError::AuthError(_) => {
+                let mut response =
+                    ProblemDetails::new(self.status_code(), "/problems/authentication-failed")
+                        .with_title("Authentication failed.")
+                        .to_response();
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                return response;
+            }
+            CreateTopicError::UnknownList => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-list")
+                    .with_title("The list does not exist.")
+                    .with_detail(self.to_string())
+            }
+            CreateTopicError::InvalidTopic(e) => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-topic")
+                    .with_title("The topic details are not valid.")
+                    .with_detail(e)
+            }
+            CreateTopicError::TopicAlreadyExists => {
+                ProblemDetails::new(self.status_code(), "/problems/topic-already-exists")
+                    .with_title("The topic already exists.")
+                    .with_detail(self.to_string())
+            }
+            CreateTopicError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "Create a topic.",
+    skip(body, list, tenant, pool, request),
+    fields(
+        topic_slug = %body.slug,
+        list_slug = %list.0,
+        tenant = %tenant.slug,
+        user_id = tracing::field::Empty
+    )
+)]
+pub async fn create_topic(
+    body: web::Json<NewTopicData>,
+    list: TargetList,
+    tenant: Tenant,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, CreateTopicError> {
+    let user_id = authenticate_basic(&request, tenant.id, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => CreateTopicError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => CreateTopicError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let NewTopicData { slug, name } = body.into_inner();
+    let slug = Slug::parse(slug).map_err(CreateTopicError::InvalidTopic)?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(CreateTopicError::InvalidTopic(
+            "The topic name cannot be empty.".into(),
+        ));
+    }
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let list = get_list(&mut transaction, tenant.id, &list.0)
+        .await
+        .context("Failed to look up the list of the topic.")?
+        .ok_or(CreateTopicError::UnknownList)?;
+    let topic = insert_topic(&mut transaction, list.id, &slug, name)
+        .await
+        .context("Failed to insert the new topic in the database.")?
+        .ok_or(CreateTopicError::TopicAlreadyExists)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new topic.")?;
+    Ok(HttpResponse::Created().json(topic))
+}
+
+/// Returns `None` if the list already has a topic with this slug.
+#[tracing::instrument(name = "Saving a new topic in the database.", skip(transaction))]
+async fn insert_topic(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    slug: &Slug,
+    name: &str,
+) -> Result<Option<Topic>, sqlx::Error> {
+    let topic = Topic {
+        id: Uuid::new_v4(),
+        slug: slug.as_ref().to_string(),
+        name: name.to_string(),
+    };
+    let result = sqlx::query!(
+        r#"
+            INSERT INTO topics (id, list_id, slug, name, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (list_id, slug) DO NOTHING
+        "#,
+        topic.id,
+        list_id,
+        topic.slug,
+        topic.name,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok((result.rows_affected() > 0).then_some(topic))
+}
@@ -0,0 +1,205 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    metrics::begin_transaction,
+    problem_details::{internal_server_error, ProblemDetails},
+    tenancy::Tenant,
+    utils::error_chain_fmt,
+};
+
+pub const WEBHOOK_SECRET_HEADER: &str = "X-Webhook-Secret";
+
+pub struct PostmarkWebhookSecret(pub Secret<String>);
+
+/// The subset of Postmark's webhook payloads we act upon.
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    #[serde(rename_all = "PascalCase")]
+    Bounce {
+        email: String,
+        #[serde(rename = "Type")]
+        bounce_type: String,
+    },
+    #[serde(rename_all = "PascalCase")]
+    SpamComplaint { email: String },
+    #[serde(rename_all = "PascalCase")]
+    Delivery { recipient: String },
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    InvalidPayload(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            WebhookError::InvalidPayload(_) => StatusCode::BAD_REQUEST,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::AuthError(_) => {
+                ProblemDetails::new(self.status_code(), "/problems/authentication-failed")
+                    .with_title("Authentication failed.")
+            }
+            WebhookError::InvalidPayload(_) => {
+                ProblemDetails::new(self.status_code(), "/problems/invalid-webhook-payload")
+                    .with_title("The webhook payload is not valid.")
+                    .with_detail(self.to_string())
+            }
+            WebhookError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+/// Ingest bounce, spam complaint and delivery notifications from Postmark.
+/// Hard bounces and spam complaints put the address on the suppression list.
+#[tracing::instrument(
+    name = "Ingest a Postmark webhook.",
+    skip(request, body, pool, secret),
+    fields(record_type = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    secret: web::Data<PostmarkWebhookSecret>,
+) -> Result<HttpResponse, WebhookError> {
+    verify_secret(&request, &secret.0).map_err(WebhookError::AuthError)?;
+    let payload: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| WebhookError::InvalidPayload(format!("Malformed JSON: {}", e)))?;
+    let record_type = payload["RecordType"]
+        .as_str()
+        .unwrap_or_default()
+        .to_owned();
+    tracing::Span::current().record("record_type", tracing::field::display(&record_type));
+    let event: PostmarkEvent = serde_json::from_value(payload.clone())
+        .map_err(|e| WebhookError::InvalidPayload(e.to_string()))?;
+    let (email, suppression_reason) = match event {
+        PostmarkEvent::Bounce { email, bounce_type } => {
+            let reason = (bounce_type == "HardBounce").then_some("hard_bounce");
+            (email, reason)
+        }
+        PostmarkEvent::SpamComplaint { email } => (email, Some("spam_complaint")),
+        PostmarkEvent::Delivery { recipient } => (recipient, None),
+        PostmarkEvent::Unsupported => {
+            tracing::info!("Ignoring an unsupported webhook event.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+    };
+    // Postmark hands back the metadata of the email the event is about.
+    let tenant_id = payload["Metadata"][Tenant::EMAIL_METADATA_KEY]
+        .as_str()
+        .and_then(|id| Uuid::parse_str(id).ok());
+    let mut transaction = begin_transaction(&pool)
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    store_event(&mut transaction, tenant_id, &record_type, &email, &payload)
+        .await
+        .context("Failed to store the email event.")?;
+    if let Some(reason) = suppression_reason {
+        suppress_email(&mut transaction, tenant_id, &email, reason)
+            .await
+            .context("Failed to add the address to the suppression list.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to store the email event.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn verify_secret(request: &HttpRequest, expected: &Secret<String>) -> Result<(), anyhow::Error> {
+    let provided = request
+        .headers()
+        .get(WEBHOOK_SECRET_HEADER)
+        .context(format!(
+            "The '{}' header was missing.",
+            WEBHOOK_SECRET_HEADER
+        ))?
+        .as_bytes();
+    // Comparing digests rather than the secrets themselves keeps the
+    // comparison time independent of how much of the secret was guessed.
+    if Sha256::digest(provided) != Sha256::digest(expected.expose_secret().as_bytes()) {
+        anyhow::bail!("Invalid webhook secret.");
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Store an email event", skip(transaction, payload))]
+async fn store_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Option<Uuid>,
+    record_type: &str,
+    email: &str,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events
+            (email_event_id, tenant_id, provider, record_type, email, payload, received_at)
+        VALUES ($1, (SELECT id FROM tenants WHERE id = $2), 'postmark', $3, lower($4), $5, $6)
+        "#,
+        Uuid::new_v4(),
+        tenant_id,
+        record_type,
+        email,
+        payload,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Suppress the address for the tenant the email was sent for.
+/// If we can't tell who that was, better to stop every tenant from mailing it
+/// than to keep mailing an address that bounces.
+#[tracing::instrument(name = "Suppress an email address", skip(transaction))]
+async fn suppress_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant_id: Option<Uuid>,
+    email: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressed_emails (tenant_id, email, reason, suppressed_at)
+        SELECT id, lower($2), $3, $4 FROM tenants
+        WHERE id = $1 OR NOT EXISTS (SELECT 1 FROM tenants WHERE id = $1)
+        ON CONFLICT (tenant_id, email) DO NOTHING
+        "#,
+        tenant_id,
+        email,
+        reason,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::ConnectionType,
+    web,
+};
+use actix_web_lab::middleware::Next;
+use tokio::sync::watch;
+
+/// Tells every part of the application that it is time to stop, and keeps
+/// track of the requests that must complete before the HTTP server stops.
+/// Cheap to clone: all clones observe the same signal.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    in_flight_requests: Arc<watch::Sender<usize>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        let (in_flight_requests, _) = watch::channel(0);
+        Self {
+            sender: Arc::new(sender),
+            in_flight_requests: Arc::new(in_flight_requests),
+        }
+    }
+
+    /// Start shutting down. Triggering it more than once has no further effect.
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once shutdown has been triggered, immediately if it already was.
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Count a request as in flight until the returned guard is dropped.
+    pub fn track_request(&self) -> InFlightRequest {
+        self.in_flight_requests.send_modify(|n| *n += 1);
+        InFlightRequest {
+            counter: self.in_flight_requests.clone(),
+        }
+    }
+
+    /// Resolves once no request is in flight.
+    pub async fn requests_drained(&self) {
+        let mut receiver = self.in_flight_requests.subscribe();
+        let _ = receiver.wait_for(|n| *n == 0).await;
+    }
+}
+
+pub struct InFlightRequest {
+    counter: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.counter.send_modify(|n| *n -= 1);
+    }
+}
+
+/// Let shutdown wait for the requests being handled and, once it has started,
+/// close connections instead of keeping them alive for more requests.
+pub async fn drain_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let shutdown = req.app_data::<web::Data<Shutdown>>().cloned();
+    let _in_flight = shutdown.as_ref().map(|s| s.track_request());
+    let mut response = next.call(req).await?;
+    if shutdown.is_some_and(|s| s.is_triggered()) {
+        response
+            .response_mut()
+            .head_mut()
+            .set_connection_type(ConnectionType::Close);
+    }
+    Ok(response)
+}
+
+/// Resolves when the process receives SIGTERM or SIGINT.
+pub async fn termination_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => tracing::info!("Received SIGINT."),
+        _ = terminate => tracing::info!("Received SIGTERM."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+
+    #[tokio::test]
+    async fn every_clone_observes_the_trigger() {
+        let shutdown = Shutdown::new();
+        let clone = shutdown.clone();
+        assert!(!clone.is_triggered());
+
+        let waiter = tokio::spawn(async move { clone.triggered().await });
+        shutdown.trigger();
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .expect("The clone was not notified.")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn waiting_after_the_trigger_returns_immediately() {
+        let shutdown = Shutdown::new();
+        shutdown.trigger();
+        shutdown.trigger();
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), shutdown.triggered())
+            .await
+            .expect("Waiting for an earlier trigger hung.");
+        assert!(shutdown.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn requests_are_drained_once_every_guard_is_dropped() {
+        let shutdown = Shutdown::new();
+        let first = shutdown.track_request();
+        let second = shutdown.clone().track_request();
+
+        drop(first);
+        assert!(tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            shutdown.requests_drained()
+        )
+        .await
+        .is_err());
+        drop(second);
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            shutdown.requests_drained(),
+        )
+        .await
+        .expect("Requests were never drained.");
+    }
+}
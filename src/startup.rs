@@ -1,75 +1,354 @@
 use std::net::TcpListener;
 
-use actix_web::{dev::Server, web, App, HttpServer};
+use actix_session::SessionMiddleware;
+use actix_web::{cookie::Key, dev::Server, web, App, HttpServer};
+use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
+use actix_web_lab::middleware::from_fn;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::task::{JoinError, JoinSet};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    authentication::{
+        provision_admin, reject_anonymous_api_clients, reject_anonymous_users, Credentials,
+    },
+    configuration::{DatabaseSettings, Settings, ShutdownSettings},
+    content_negotiation::ResponseFormat,
     email_client::EmailClient,
-    routes,
+    idempotency::idempotency,
+    issue_delivery_worker::IssueDeliveryWorker,
+    metrics::record_http_metrics,
+    problem_details::invalid_request_handler,
+    rate_limiting::rate_limit,
+    retention_worker::RetentionWorker,
+    routes::{self, PostmarkWebhookSecret},
+    session_store::PgSessionStore,
+    shutdown::{drain_requests, termination_signal, Shutdown},
+    tenancy::Tenants,
 };
 
 pub struct Application {
     port: u16,
     server: Server,
+    issue_delivery_worker: IssueDeliveryWorker,
+    retention_worker: RetentionWorker,
+    connection_pool: PgPool,
+    shutdown: Shutdown,
+    shutdown_settings: ShutdownSettings,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        if let Some(admin) = configuration.application.initial_admin.clone() {
+            let credentials = Credentials {
+                username: admin.username,
+                password: admin.password,
+            };
+            provision_admin(credentials, &connection_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+        let email_client = configuration.email_client.clone().client();
+        let issue_delivery_worker = IssueDeliveryWorker::build(configuration.clone());
+        let retention_worker = RetentionWorker::build(configuration.clone());
         let address = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(listener, connection_pool, email_client)?;
-        Ok(Self { port, server })
+        let shutdown = Shutdown::new();
+        let shutdown_settings = configuration.shutdown.clone();
+        let server = run(
+            listener,
+            connection_pool.clone(),
+            email_client,
+            shutdown.clone(),
+            configuration,
+        )?;
+        Ok(Self {
+            port,
+            server,
+            issue_delivery_worker,
+            retention_worker,
+            connection_pool,
+            shutdown,
+            shutdown_settings,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Triggering the returned handle has the same effect as sending SIGTERM.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Run the HTTP server and the background workers until a termination signal
+    /// is received, the shutdown handle is triggered or any of them stops, then
+    /// let in-flight requests and jobs finish before closing the database pool.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let server_handle = self.server.handle();
+        let mut components = JoinSet::new();
+        components.spawn(self.server);
+        components.spawn(
+            self.issue_delivery_worker
+                .run_until_stopped(self.shutdown.clone()),
+        );
+        components.spawn(
+            self.retention_worker
+                .run_until_stopped(self.shutdown.clone()),
+        );
+        let early_exit = tokio::select! {
+            // Workers stop by themselves once shutdown is triggered: that is not an early exit.
+            biased;
+            _ = termination_signal() => None,
+            _ = self.shutdown.triggered() => None,
+            Some(outcome) = components.join_next() => {
+                tracing::warn!("A component of the application stopped on its own.");
+                Some(flatten(outcome))
+            }
+        };
+
+        tracing::info!("Shutting down.");
+        // Readiness fails from now on, but keep serving while the load balancer notices.
+        self.shutdown.trigger();
+        tokio::time::sleep(self.shutdown_settings.grace_period()).await;
+        // Stop accepting connections, then stop the server once in-flight requests are done.
+        // Waiting on actix's own graceful stop is not enough: its workers can exit as soon
+        // as the acceptor goes away, dropping the connections they are still serving.
+        server_handle.pause().await;
+        let drained = tokio::time::timeout(self.shutdown_settings.drain_timeout(), async {
+            self.shutdown.requests_drained().await;
+            server_handle.stop(true).await;
+            let mut outcome = Ok(());
+            while let Some(component_outcome) = components.join_next().await {
+                outcome = outcome.and(flatten(component_outcome));
+            }
+            outcome
+        })
+        .await;
+        let outcome = match drained {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                tracing::warn!(
+                    "In-flight work did not complete within {}s, aborting it.",
+                    self.shutdown_settings.drain_timeout_seconds
+                );
+                components.shutdown().await;
+                Ok(())
+            }
+        };
+        self.connection_pool.close().await;
+        tracing::info!("Shutdown complete.");
+        early_exit.unwrap_or(outcome)
     }
 }
 
+fn flatten(outcome: Result<Result<(), std::io::Error>, JoinError>) -> Result<(), std::io::Error> {
+    outcome.map_err(std::io::Error::other)?
+}
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .connect_timeout(std::time::Duration::from_secs(10))
         .connect_lazy_with(configuration.with_db())
 }
 
+pub struct UnsubscribeSecret(pub Secret<String>);
+
 pub fn run(
     listener: TcpListener,
     connection_pool: PgPool,
     email_client: EmailClient,
+    shutdown: Shutdown,
+    configuration: Settings,
 ) -> Result<Server, std::io::Error> {
+    let secret_key = Key::from(
+        configuration
+            .application
+            .hmac_secret
+            .expose_secret()
+            .as_bytes(),
+    );
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
+    let session_store = PgSessionStore::new(connection_pool.clone());
+    let bot_protection = web::Data::new(configuration.application.bot_protection.protection());
+    let rate_limiter = web::Data::new(configuration.application.rate_limiting.limiter(
+        connection_pool.clone(),
+        configuration.application.hmac_secret.clone(),
+    ));
+    let tenants = web::Data::new(Tenants::new(
+        connection_pool.clone(),
+        configuration.application.base_url,
+        configuration.email_client.clone(),
+        email_client.clone(),
+    ));
     let connection = web::Data::new(connection_pool);
     let email_client = web::Data::new(email_client);
+    let unsubscribe_secret = web::Data::new(UnsubscribeSecret(
+        configuration.application.unsubscribe_secret,
+    ));
+    let webhook_secret = web::Data::new(PostmarkWebhookSecret(
+        configuration.email_client.webhook_secret,
+    ));
+    let subscription_tokens = web::Data::new(configuration.subscription_tokens);
+    let health_settings = web::Data::new(configuration.health);
+    let shutdown = web::Data::new(shutdown);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(idempotency))
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(record_http_metrics))
+            .wrap(from_fn(drain_requests))
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(routes::health_check))
-            .route("/subscriptions", web::post().to(routes::subscribe))
+            .route("/health/live", web::get().to(routes::liveness))
+            .route("/health/ready", web::get().to(routes::readiness))
+            .route("/metrics", web::get().to(routes::metrics))
+            .configure(tenant_routes)
+            .service(web::scope("/tenants/{tenant_slug}").configure(tenant_routes))
+            .route(
+                "/webhooks/email/postmark",
+                web::post().to(routes::postmark_webhook),
+            )
+            .route("/login", web::get().to(routes::login_form))
+            .route("/login", web::post().to(routes::login))
+            // Registered before "/admin", which would match its paths too.
+            .service(
+                web::scope("/admin/subscribers")
+                    .wrap(from_fn(reject_anonymous_api_clients))
+                    .route("", web::get().to(routes::admin_list_subscribers))
+                    .route(
+                        "/{subscriber_id}",
+                        web::get().to(routes::admin_get_subscriber),
+                    )
+                    .route(
+                        "/{subscriber_id}",
+                        web::patch().to(routes::admin_update_subscriber),
+                    )
+                    .route(
+                        "/{subscriber_id}",
+                        web::delete().to(routes::admin_delete_subscriber),
+                    ),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(routes::admin_dashboard))
+                    .route("/logout", web::post().to(routes::log_out))
+                    .route("/password", web::get().to(routes::change_password_form))
+                    .route("/password", web::post().to(routes::change_password)),
+            )
+            .app_data(web::FormConfig::default().error_handler(invalid_request_handler))
+            .app_data(web::JsonConfig::default().error_handler(invalid_request_handler))
+            .app_data(web::QueryConfig::default().error_handler(invalid_request_handler))
             .app_data(connection.clone())
             .app_data(email_client.clone())
+            .app_data(tenants.clone())
+            .app_data(unsubscribe_secret.clone())
+            .app_data(webhook_secret.clone())
+            .app_data(subscription_tokens.clone())
+            .app_data(health_settings.clone())
+            .app_data(shutdown.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bot_protection.clone())
     })
+    // Signals are handled by `Application`, to shut the workers down too.
+    .disable_signals()
+    .shutdown_timeout(configuration.shutdown.drain_timeout_seconds)
     .listen(listener)?
     .run();
     Ok(server)
 }
+
+/// The routes each tenant gets, both on its own host and under `/tenants/{tenant_slug}`.
+fn tenant_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/subscriptions")
+            .app_data(routes::SubscriptionFormTarget)
+            .route(web::post().to(routes::subscribe)),
+    )
+    .route(
+        "/subscriptions/new",
+        web::get().to(routes::subscription_form),
+    )
+    .route("/subscriptions/confirm", web::get().to(routes::confirm))
+    .route(
+        "/subscriptions/unsubscribe",
+        web::get().to(routes::unsubscribe_form),
+    )
+    .route(
+        "/subscriptions/unsubscribe",
+        web::post().to(routes::unsubscribe),
+    )
+    .route(
+        "/subscriptions/preferences",
+        web::get().to(routes::preferences),
+    )
+    .route(
+        "/subscriptions/preferences",
+        web::post().to(routes::update_preferences),
+    )
+    .route(
+        "/subscriptions/me/requests",
+        web::post().to(routes::request_subscriber_data),
+    )
+    .route(
+        "/subscriptions/me/export",
+        web::get().to(routes::export_subscriber_data),
+    )
+    .route(
+        "/subscriptions/me/erase",
+        web::get().to(routes::erasure_form),
+    )
+    .route(
+        "/subscriptions/me/erase",
+        web::post().to(routes::confirm_erasure),
+    )
+    .route(
+        "/subscriptions/me",
+        web::delete().to(routes::erase_subscriber_data),
+    )
+    .route("/newsletters", web::post().to(routes::publish_newsletter))
+    .route("/lists", web::post().to(routes::create_list))
+    .route(
+        "/lists/{list_slug}/subscriptions",
+        web::post().to(routes::subscribe),
+    )
+    .route(
+        "/lists/{list_slug}/newsletters",
+        web::post().to(routes::publish_newsletter),
+    )
+    .route(
+        "/lists/{list_slug}/topics",
+        web::post().to(routes::create_topic),
+    )
+    .service(
+        web::scope("/api/v1")
+            .app_data(ResponseFormat::Json)
+            .route("/subscriptions", web::post().to(routes::subscribe))
+            .route(
+                "/lists/{list_slug}/subscriptions",
+                web::post().to(routes::subscribe),
+            )
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(routes::preferences),
+            )
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(routes::update_preferences),
+            ),
+    );
+}
@@ -1,13 +1,27 @@
+use opentelemetry::{global, propagation::Injector, trace::TraceError, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Config, Sampler, Tracer, TracerProvider},
+    Resource,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::TelemetrySettings;
+
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Tracer,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -19,9 +33,156 @@ where
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// Build the provider behind the OpenTelemetry layer of `get_subscriber`.
+/// Spans are only exported when OTLP is enabled, but they always carry
+/// a trace context that we propagate to the services we call.
+///
+/// Tracers only hold a weak reference to their provider: keep it alive
+/// (e.g. with `opentelemetry::global::set_tracer_provider`) for as long as
+/// spans are being recorded, and flush it before exiting.
+pub fn build_tracer_provider(settings: &TelemetrySettings) -> Result<TracerProvider, TraceError> {
+    let config = Config::default()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sampling_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]));
+    let mut builder = TracerProvider::builder().with_config(config);
+    if settings.otlp_enabled {
+        let exporter = opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(&settings.otlp_endpoint)
+            .build_span_exporter()?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+    Ok(builder.build())
+}
+
+/// W3C trace context headers (`traceparent`, `tracestate`) identifying the current span,
+/// to be attached to outgoing HTTP requests.
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Run a CPU-bound closure on tokio's blocking thread pool,
+/// attaching it to the span of the caller.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{build_tracer_provider, get_subscriber};
+    use crate::configuration::TelemetrySettings;
+
+    fn settings(otlp_endpoint: String) -> TelemetrySettings {
+        TelemetrySettings {
+            otlp_enabled: true,
+            otlp_endpoint,
+            service_name: "zero2prod-test".into(),
+            sampling_ratio: 1.0,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_otlp_collector() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .and(header("Content-Type", "application/x-protobuf"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let tracer_provider = build_tracer_provider(&settings(collector.uri())).unwrap();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Exported span").in_scope(|| {});
+        });
+        tokio::task::spawn_blocking(move || tracer_provider.force_flush())
+            .await
+            .unwrap();
+
+        // Assert
+        // Mock asserts on drop
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nothing_is_exported_when_otlp_is_disabled() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&collector)
+            .await;
+        let mut settings = settings(collector.uri());
+        settings.otlp_enabled = false;
+        let tracer_provider = build_tracer_provider(&settings).unwrap();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Unexported span").in_scope(|| {});
+        });
+        tokio::task::spawn_blocking(move || tracer_provider.force_flush())
+            .await
+            .unwrap();
+
+        // Assert
+        // Mock asserts on drop
+    }
 }
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Mutex};
+
+use actix_web::{
+    dev::Payload, http::StatusCode, web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{EmailClientSettings, EmailProvider},
+    domain::SubscriberEmail,
+    email_client::{EmailClient, PostmarkTransport, SuppressionList},
+    problem_details::{internal_server_error, ProblemDetails},
+    utils::error_chain_fmt,
+};
+
+/// One of the independent newsletters hosted by this deployment, with its own
+/// lists, subscribers and sender identity.
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    pub id: Uuid,
+    pub slug: String,
+    base_url: String,
+    email_client: EmailClient,
+}
+
+impl Tenant {
+    /// Owns everything that predates tenants. Requests that don't name a tenant,
+    /// by path or by host, are served on its behalf.
+    pub const DEFAULT: &'static str = "default";
+
+    /// Attached to the emails sent on behalf of the tenant, so that the events the
+    /// provider reports about them can be traced back to the tenant.
+    pub const EMAIL_METADATA_KEY: &'static str = "tenant_id";
+
+    /// Where the tenant's pages live, for the links in the emails we send.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Sends emails from the tenant's address, through the tenant's provider account.
+    pub fn email_client(&self) -> &EmailClient {
+        &self.email_client
+    }
+}
+
+/// Looks tenants up and equips them with an email client.
+pub struct Tenants {
+    pool: PgPool,
+    base_url: String,
+    email_settings: EmailClientSettings,
+    default_email_client: EmailClient,
+    /// Keyed by authorization token: a tenant whose token changes gets a new one.
+    postmark_transports: Mutex<HashMap<String, PostmarkTransport>>,
+}
+
+struct TenantRecord {
+    id: Uuid,
+    slug: String,
+    base_url: Option<String>,
+    sender_email: Option<String>,
+    email_authorization_token: Option<String>,
+}
+
+impl Tenants {
+    /// Tenants without a sender or credentials of their own use those of
+    /// `default_email_client`.
+    pub fn new(
+        pool: PgPool,
+        base_url: String,
+        email_settings: EmailClientSettings,
+        default_email_client: EmailClient,
+    ) -> Self {
+        Self {
+            pool,
+            base_url,
+            email_settings,
+            default_email_client,
+            postmark_transports: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The tenant named by `slug` if any, otherwise the one serving `host`,
+    /// falling back to the default tenant.
+    /// Returns `None` if there is no tenant with the given slug.
+    #[tracing::instrument(name = "Resolve the tenant of a request.", skip(self))]
+    pub async fn resolve(
+        &self,
+        slug: Option<&str>,
+        host: &str,
+    ) -> Result<Option<Tenant>, anyhow::Error> {
+        let record = match slug {
+            Some(slug) => sqlx::query_as!(
+                TenantRecord,
+                r#"
+                    SELECT id, slug, base_url, sender_email, email_authorization_token
+                    FROM tenants
+                    WHERE slug = $1
+                "#,
+                slug
+            )
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to look up the tenant by slug.")?,
+            None => sqlx::query_as!(
+                TenantRecord,
+                r#"
+                    SELECT id, slug, base_url, sender_email, email_authorization_token
+                    FROM tenants
+                    WHERE host = $1 OR slug = $2
+                    ORDER BY slug = $2
+                    LIMIT 1
+                "#,
+                strip_port(host).to_lowercase(),
+                Tenant::DEFAULT
+            )
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to look up the tenant by host.")?,
+        };
+        record.map(|record| self.tenant(record)).transpose()
+    }
+
+    #[tracing::instrument(name = "Get a tenant.", skip(self))]
+    pub async fn get(&self, tenant_id: Uuid) -> Result<Tenant, anyhow::Error> {
+        let record = sqlx::query_as!(
+            TenantRecord,
+            r#"
+                SELECT id, slug, base_url, sender_email, email_authorization_token
+                FROM tenants
+                WHERE id = $1
+            "#,
+            tenant_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to look up the tenant.")?;
+        self.tenant(record)
+    }
+
+    fn tenant(&self, record: TenantRecord) -> Result<Tenant, anyhow::Error> {
+        let base_url = match record.base_url {
+            Some(base_url) => base_url,
+            None if record.slug == Tenant::DEFAULT => self.base_url.clone(),
+            None => format!("{}/tenants/{}", self.base_url, record.slug),
+        };
+        let mut email_client = self
+            .default_email_client
+            .with_metadata(Tenant::EMAIL_METADATA_KEY, record.id.to_string())
+            .with_suppression_list(SuppressionList::new(self.pool.clone(), record.id));
+        if let Some(sender_email) = record.sender_email {
+            let sender = SubscriberEmail::parse(sender_email).map_err(anyhow::Error::msg)?;
+            email_client = email_client.with_sender(sender);
+        }
+        // Like `EmailClientSettings::authorization_token`, only Postmark needs one.
+        if let Some(token) = record.email_authorization_token {
+            if self.email_settings.provider == EmailProvider::Postmark {
+                email_client = email_client.with_transport(self.postmark_transport(token));
+            }
+        }
+        Ok(Tenant {
+            id: record.id,
+            slug: record.slug,
+            base_url,
+            email_client,
+        })
+    }
+
+    /// Reuse transports, and the connection pools of their HTTP clients, across requests.
+    fn postmark_transport(&self, authorization_token: String) -> PostmarkTransport {
+        let mut transports = self.postmark_transports.lock().unwrap();
+        transports
+            .entry(authorization_token)
+            .or_insert_with_key(|token| {
+                PostmarkTransport::new(
+                    self.email_settings.base_url.clone(),
+                    Secret::new(token.clone()),
+                    self.email_settings.timeout(),
+                    self.email_settings.retry.policy(),
+                )
+            })
+            .clone()
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((hostname, port)) if port.parse::<u16>().is_ok() => hostname,
+        _ => host,
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum TenantError {
+    #[error("There is no tenant with this slug.")]
+    UnknownTenant,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TenantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TenantError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TenantError::UnknownTenant => StatusCode::NOT_FOUND,
+            TenantError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            TenantError::UnknownTenant => {
+                ProblemDetails::new(self.status_code(), "/problems/unknown-tenant")
+                    .with_title("The tenant does not exist.")
+                    .with_detail(self.to_string())
+            }
+            TenantError::UnexpectedError(_) => internal_server_error(),
+        }
+        .to_response()
+    }
+}
+
+/// Named by the `{tenant_slug}` segment of the path, or served on the requested host.
+impl FromRequest for Tenant {
+    type Error = TenantError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let tenants = req.app_data::<web::Data<Tenants>>().cloned();
+        let slug = req.match_info().get("tenant_slug").map(ToOwned::to_owned);
+        let host = req.connection_info().host().to_owned();
+        Box::pin(async move {
+            let tenants = tenants.context("The tenants are not configured.")?;
+            tenants
+                .resolve(slug.as_deref(), &host)
+                .await?
+                .ok_or(TenantError::UnknownTenant)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_port;
+
+    #[test]
+    fn the_port_is_stripped_from_the_host() {
+        assert_eq!(strip_port("news.example.com:8000"), "news.example.com");
+        assert_eq!(strip_port("news.example.com"), "news.example.com");
+        assert_eq!(strip_port("[::1]:8000"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+}
@@ -0,0 +1,483 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::problem_details::ProblemDetails;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+/// Subscribe `name` <`email`> to the default list and return their id.
+async fn subscribe(app: &TestApp, name: &str, email: &str) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_api_subscriptions(serde_json::json!({ "name": name, "email": email }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["subscriber_id"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn get_subscribers(app: &TestApp, query: &[(&str, &str)]) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/subscribers", app.address))
+        .query(query)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_subscriber(app: &TestApp, id: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/subscribers/{}", app.address, id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn patch_subscriber(app: &TestApp, id: &str, body: serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .patch(format!("{}/admin/subscribers/{}", app.address, id))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn delete_subscriber(app: &TestApp, id: &str) -> reqwest::Response {
+    app.api_client
+        .delete(format!("{}/admin/subscribers/{}", app.address, id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn emails(app: &TestApp, query: &[(&str, &str)]) -> Vec<String> {
+    let page: serde_json::Value = get_subscribers(app, query)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = uuid::Uuid::new_v4().to_string();
+    // Act
+    let responses = vec![
+        get_subscribers(&app, &[]).await,
+        get_subscriber(&app, &id).await,
+        patch_subscriber(&app, &id, serde_json::json!({ "name": "Ursula" })).await,
+        delete_subscriber(&app, &id).await,
+    ];
+    // Assert
+    for response in responses {
+        assert_eq!(response.status().as_u16(), 401);
+        let problem: ProblemDetails = response.json().await.unwrap();
+        assert_eq!(problem.problem_type, "/problems/not-logged-in");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let ursula = subscribe(&app, "Ursula Le Guin", "ursula_le_guin@gmail.com").await;
+    subscribe(&app, "Octavia Butler", "octavia@example.com").await;
+    patch_subscriber(
+        &app,
+        &ursula,
+        serde_json::json!({ "lists": { "newsletter": "unsubscribed" } }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let tomorrow = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+    let test_cases = vec![
+        (
+            vec![],
+            vec!["ursula_le_guin@gmail.com", "octavia@example.com"],
+        ),
+        (
+            vec![("status", "unsubscribed")],
+            vec!["ursula_le_guin@gmail.com"],
+        ),
+        (
+            vec![("status", "pending_confirmation")],
+            vec!["octavia@example.com"],
+        ),
+        (vec![("q", "BUTLER")], vec!["octavia@example.com"]),
+        (vec![("q", "Ursula_Le")], vec!["ursula_le_guin@gmail.com"]),
+        (
+            vec![("list", "newsletter"), ("status", "confirmed")],
+            vec![],
+        ),
+        (vec![("list", "missing")], vec![]),
+        (
+            vec![("tenant", "default"), ("q", "octavia")],
+            vec!["octavia@example.com"],
+        ),
+        (vec![("subscribed_after", tomorrow.as_str())], vec![]),
+        (
+            vec![("subscribed_before", tomorrow.as_str())],
+            vec!["ursula_le_guin@gmail.com", "octavia@example.com"],
+        ),
+    ];
+    for (query, expected) in test_cases {
+        // Act
+        let emails = emails(&app, &query).await;
+        // Assert
+        assert_eq!(emails, expected, "Query: {:?}", query);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_in_the_order_they_subscribed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let mut subscribed = vec![];
+    for i in 0..5 {
+        let email = format!("subscriber{}@example.com", i);
+        subscribe(&app, &format!("Subscriber {}", i), &email).await;
+        subscribed.push(email);
+    }
+    // Act
+    let mut listed = vec![];
+    let mut cursor: Option<String> = None;
+    let mut n_pages = 0;
+    loop {
+        let mut query = vec![("limit", "2".to_string())];
+        if let Some(cursor) = &cursor {
+            query.push(("cursor", cursor.clone()));
+        }
+        let query: Vec<_> = query.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let page: serde_json::Value = get_subscribers(&app, &query)
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        n_pages += 1;
+        for subscriber in page["subscribers"].as_array().unwrap() {
+            listed.push(subscriber["email"].as_str().unwrap().to_owned());
+        }
+        match page["next_cursor"].as_str() {
+            Some(next_cursor) => cursor = Some(next_cursor.to_owned()),
+            None => break,
+        }
+    }
+    // Assert
+    assert_eq!(n_pages, 3);
+    assert_eq!(listed, subscribed);
+}
+
+#[tokio::test]
+async fn invalid_queries_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        ("status", "bogus"),
+        ("limit", "0"),
+        ("limit", "1000"),
+        ("cursor", "not-a-cursor"),
+        ("subscribed_after", "yesterday"),
+    ];
+    for (key, value) in test_cases {
+        // Act
+        let response = get_subscribers(&app, &[(key, value)]).await;
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "Query: {}={}", key, value);
+    }
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_inspected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let id = subscribe(&app, "Ursula Le Guin", "ursula_le_guin@gmail.com").await;
+    // Act
+    let response = get_subscriber(&app, &id).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["id"], id);
+    assert_eq!(subscriber["tenant"], "default");
+    assert_eq!(subscriber["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscriber["name"], "Ursula Le Guin");
+    assert_eq!(subscriber["lists"][0]["list"], "newsletter");
+    assert_eq!(subscriber["lists"][0]["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_reported_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let id = uuid::Uuid::new_v4().to_string();
+    // Act
+    let responses = vec![
+        get_subscriber(&app, &id).await,
+        patch_subscriber(&app, &id, serde_json::json!({ "name": "Ursula" })).await,
+        delete_subscriber(&app, &id).await,
+    ];
+    // Assert
+    for response in responses {
+        assert_eq!(response.status().as_u16(), 404);
+        let problem: ProblemDetails = response.json().await.unwrap();
+        assert_eq!(problem.problem_type, "/problems/unknown-subscriber");
+    }
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_updated() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let id = subscribe(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    // Act
+    let response = patch_subscriber(
+        &app,
+        &id,
+        serde_json::json!({
+            "name": "Ursula K. Le Guin",
+            "email": "ursula@example.com",
+            "lists": { "newsletter": "unsubscribed" }
+        }),
+    )
+    .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["name"], "Ursula K. Le Guin");
+    assert_eq!(subscriber["email"], "ursula@example.com");
+    assert_eq!(subscriber["lists"][0]["status"], "unsubscribed");
+}
+
+#[tokio::test]
+async fn subscribers_moved_back_to_pending_get_a_new_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+    let id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+        .to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = patch_subscriber(
+        &app,
+        &id,
+        serde_json::json!({ "lists": { "newsletter": "pending_confirmation" } }),
+    )
+    .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["lists"][0]["status"], "pending_confirmation");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let subscriber: serde_json::Value = get_subscriber(&app, &id).await.json().await.unwrap();
+    assert_eq!(subscriber["lists"][0]["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_must_confirm_their_new_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    create_confirmed_subscriber(&app).await;
+    let id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+        .to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = patch_subscriber(
+        &app,
+        &id,
+        serde_json::json!({ "email": "ursula@example.com" }),
+    )
+    .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["lists"][0]["status"], "pending_confirmation");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula@example.com");
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let subscriber: serde_json::Value = get_subscriber(&app, &id).await.json().await.unwrap();
+    assert_eq!(subscriber["lists"][0]["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn invalid_updates_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let id = subscribe(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    let test_cases = vec![
+        (serde_json::json!({ "name": "" }), "name"),
+        (serde_json::json!({ "email": "not-an-email" }), "email"),
+        (
+            serde_json::json!({ "lists": { "newsletter": "bogus" } }),
+            "lists.newsletter",
+        ),
+        (
+            serde_json::json!({ "lists": { "newsletter": "confirmed" } }),
+            "lists.newsletter",
+        ),
+        (
+            serde_json::json!({ "lists": { "missing": "confirmed" } }),
+            "lists.missing",
+        ),
+    ];
+    for (body, field) in test_cases {
+        // Act
+        let response = patch_subscriber(&app, &id, body.clone()).await;
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "Body: {}", body);
+        let problem: ProblemDetails = response.json().await.unwrap();
+        assert!(problem.errors.contains_key(field), "Body: {}", body);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_of_tenants_the_user_is_not_a_member_of_are_out_of_reach() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let tenant_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO tenants (id, slug, created_at) VALUES ($1, 'acme', now())",
+        tenant_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let subscriber_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriptions (id, tenant_id, email, name, subscribed_at)
+            VALUES ($1, $2, 'octavia@example.com', 'Octavia Butler', now())
+        "#,
+        subscriber_id,
+        tenant_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let id = subscriber_id.to_string();
+    // Act
+    let responses = vec![
+        get_subscriber(&app, &id).await,
+        patch_subscriber(&app, &id, serde_json::json!({ "name": "Ursula" })).await,
+        delete_subscriber(&app, &id).await,
+    ];
+    // Assert
+    for response in responses {
+        assert_eq!(response.status().as_u16(), 404);
+    }
+    assert!(emails(&app, &[("tenant", "acme")]).await.is_empty());
+    let saved = sqlx::query!("SELECT name, erased_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Octavia Butler");
+    assert!(saved.erased_at.is_none());
+}
+
+#[tokio::test]
+async fn email_addresses_stay_unique_within_a_tenant() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let id = subscribe(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    subscribe(&app, "Octavia Butler", "octavia@example.com").await;
+    // Act
+    let response = patch_subscriber(
+        &app,
+        &id,
+        serde_json::json!({ "email": "octavia@example.com" }),
+    )
+    .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn deleted_subscribers_are_erased() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let id = subscribe(&app, "le guin", "ursula_le_guin@gmail.com").await;
+    // Act
+    let response = delete_subscriber(&app, &id).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(get_subscriber(&app, &id).await.status().as_u16(), 404);
+    assert!(emails(&app, &[]).await.is_empty());
+    let saved = sqlx::query!("SELECT email, erased_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!saved.email.contains("ursula"));
+    assert!(saved.erased_at.is_some());
+}
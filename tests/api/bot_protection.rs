@@ -0,0 +1,230 @@
+use chrono::{Duration, Utc};
+use reqwest::Client;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::{configuration::CaptchaProvider, domain::FormToken};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+/// Where the test client connects from.
+const CLIENT_IP: &str = "127.0.0.1";
+
+impl TestApp {
+    /// A token for a form rendered `ago` for the test client.
+    fn form_token(&self, ago: Duration) -> String {
+        FormToken::generate(Utc::now() - ago, CLIENT_IP, &self.form_token_secret)
+            .as_ref()
+            .to_owned()
+    }
+}
+
+fn subscription_body(extra_fields: &[(&str, &str)]) -> String {
+    // Borrow the form encoding of query strings.
+    let mut url = reqwest::Url::parse("http://localhost").unwrap();
+    url.query_pairs_mut()
+        .extend_pairs([("name", "le guin"), ("email", "ursula_le_guin@gmail.com")])
+        .extend_pairs(extra_fields);
+    url.query().unwrap().to_owned()
+}
+
+async fn n_subscriptions(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn submissions_giving_themselves_away_are_accepted_but_discarded() {
+    // Arrange
+    let app = spawn_app().await;
+    let too_fast = app.form_token(Duration::zero());
+    let test_cases = vec![
+        (
+            vec![("website", "https://spam.example.com")],
+            "filled the honeypot",
+        ),
+        (
+            vec![("form_token", too_fast.as_str())],
+            "submitted faster than a human",
+        ),
+        (
+            vec![("form_token", "1700000000.deadbeef")],
+            "forged the form token",
+        ),
+    ];
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (extra_fields, description) in test_cases {
+        // Act
+        let response = app
+            .post_subscriptions(subscription_body(&extra_fields))
+            .await;
+
+        // Assert
+        assert_eq!(
+            200,
+            response.status().as_u16(),
+            "The API did not pretend to accept a submission that {}.",
+            description
+        );
+    }
+    assert_eq!(n_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn submissions_from_a_form_filled_by_a_person_are_saved() {
+    // Arrange
+    let app = spawn_app().await;
+    let form_token = app.form_token(Duration::seconds(30));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(subscription_body(&[
+            ("website", ""),
+            ("form_token", &form_token),
+        ]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_subscriptions(&app).await, 1);
+}
+
+#[tokio::test]
+async fn submitting_an_expired_form_returns_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let form_token = app.form_token(Duration::days(2));
+
+    // Act
+    let response = app
+        .post_subscriptions(subscription_body(&[("form_token", &form_token)]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/expired-form");
+}
+
+#[tokio::test]
+async fn submissions_without_a_form_token_are_discarded_when_it_is_required() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.bot_protection.require_form_token = true).await;
+
+    // Act
+    let response = app.post_subscriptions(subscription_body(&[])).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn api_and_list_signups_do_not_need_a_form_token_when_it_is_required() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.bot_protection.require_form_token = true;
+        c.application.rate_limiting.per_email.capacity = 10;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let json_response = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+    let list_response = app
+        .post_list_subscriptions(
+            "newsletter",
+            "name=octavia%20butler&email=octavia%40example.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(json_response.status().as_u16(), 200);
+    assert_eq!(list_response.status().as_u16(), 200);
+    assert_eq!(n_subscriptions(&app).await, 2);
+}
+
+#[tokio::test]
+async fn the_captcha_must_be_solved_when_enabled() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let captcha = &mut c.application.bot_protection.captcha;
+        captcha.provider = CaptchaProvider::Fake;
+        captcha.fake_solution = "solved".into();
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Wrong solution
+    let response = app
+        .post_subscriptions(subscription_body(&[("captcha_response", "guessed")]))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/captcha-failed");
+
+    // Act - Part 2 - Right solution
+    let response = app
+        .post_subscriptions(subscription_body(&[("captcha_response", "solved")]))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_subscriptions(&app).await, 1);
+}
+
+#[tokio::test]
+async fn the_subscription_form_embeds_a_valid_form_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html = Client::new()
+        .get(format!("{}/subscriptions/new", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    let form_token = html
+        .split(r#"name="form_token" value=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("The form does not embed a form token.");
+    let issued_at = FormToken::verify(form_token, CLIENT_IP, &app.form_token_secret).unwrap();
+    assert!(Utc::now() - issued_at < Duration::minutes(1));
+    assert!(html.contains(r#"name="website""#));
+}
@@ -9,7 +9,7 @@ async fn health_check_works() {
     let client = Client::new();
     // Act
     let response = client
-        .get(&format!("{}/health_check", &test_app.address))
+        .get(format!("{}/health_check", &test_app.address))
         .send()
         .await
         .expect("Failed to execute test");
@@ -17,3 +17,70 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_returns_200() {
+    // Arrange
+    let test_app = spawn_app().await;
+    // Act
+    let response = Client::new()
+        .get(format!("{}/health/live", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_reports_the_status_of_each_dependency() {
+    // Arrange
+    let test_app = spawn_app().await;
+    // Act
+    let response = Client::new()
+        .get(format!("{}/health/ready", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    for component in ["database", "migrations"] {
+        assert_eq!(body["components"][component]["status"], "up");
+        assert!(body["components"][component]["latency_ms"].is_u64());
+    }
+    // Probing the email provider is disabled by default.
+    assert!(body["components"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn readiness_returns_503_if_migrations_are_missing() {
+    // Arrange
+    let test_app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)"
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+    // Act
+    let response = Client::new()
+        .get(format!("{}/health/ready", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["migrations"]["status"], "down");
+    // What went wrong is logged, not exposed.
+    assert_eq!(
+        body["components"]["migrations"]["error"],
+        "The check failed."
+    );
+}
@@ -1,64 +1,527 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use opentelemetry::trace::TracerProvider as _;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{get_configuration, DatabaseSettings, EmailProvider, Settings},
+    issue_delivery_worker::{ExecutionOutcome, IssueDeliveryWorker},
+    retention_worker::RetentionWorker,
+    shutdown::Shutdown,
     startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{build_tracer_provider, get_subscriber, init_subscriber},
 };
-use wiremock::MockServer;
 
 pub struct TestApp {
     pub address: String,
+    pub port: u16,
     pub db_pool: PgPool,
+    pub database: DatabaseSettings,
     pub email_server: MockServer,
+    pub issue_delivery_worker: IssueDeliveryWorker,
+    pub retention_worker: RetentionWorker,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    pub webhook_secret: String,
+    pub form_token_secret: Secret<String>,
+    pub shutdown: Shutdown,
+    pub application: JoinHandle<Result<(), std::io::Error>>,
+}
+
+/// actix's workers keep the application's connection pool alive after the test is over.
+/// Dropping the database closes its connections before they exhaust those Postgres allows.
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let database = self.database.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                if let Ok(mut connection) = PgConnection::connect_with(&database.without_db()).await
+                {
+                    let _ = connection
+                        .execute(
+                            format!(
+                                r#"DROP DATABASE "{}" WITH (FORCE);"#,
+                                database.database_name
+                            )
+                            .as_str(),
+                        )
+                        .await;
+                }
+            });
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // Match the production parameters, we only care about the algorithm here.
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash)
+            VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+        self.join_tenant(pool, "default").await;
+    }
+
+    /// Allow the user to act on behalf of the tenant through the API.
+    pub async fn join_tenant(&self, pool: &PgPool, tenant_slug: &str) {
+        sqlx::query!(
+            "INSERT INTO tenant_members (tenant_id, user_id)
+            SELECT id, $1 FROM tenants WHERE slug = $2",
+            self.user_id,
+            tenant_slug,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to add the test user to the tenant.");
+    }
+}
+
+/// Confirmation links embedded in the request to the email API.
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
 }
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_subscriptions(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/subscriptions", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscriptions_with_idempotency_key(
+        &self,
+        body: String,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", idempotency_key)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_lists(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_list_subscriptions(
+        &self,
+        list_slug: &str,
+        body: String,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/lists/{}/subscriptions",
+                &self.address, list_slug
+            ))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
+
+    pub async fn post_list_newsletters(
+        &self,
+        list_slug: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists/{}/newsletters", &self.address, list_slug))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_topics(&self, list_slug: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists/{}/topics", &self.address, list_slug))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email/postmark", &self.address))
+            .header("X-Webhook-Secret", &self.webhook_secret)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn login_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await;
+    }
+
+    /// Trigger a graceful shutdown and wait for the application to stop.
+    pub async fn shut_down(&mut self) -> Result<(), std::io::Error> {
+        self.shutdown.trigger();
+        (&mut self.application)
+            .await
+            .expect("The application panicked.")
+    }
+
+    /// Drain the delivery queue of all the tasks that are due.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            let outcome = self.issue_delivery_worker.try_execute_task().await.unwrap();
+            if let ExecutionOutcome::EmptyQueue = outcome {
+                // The background worker might still be holding a lock on a task.
+                if self.n_pending_delivery_tasks().await == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    async fn n_pending_delivery_tasks(&self) -> i64 {
+        sqlx::query!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM issue_delivery_queue
+                WHERE execute_after <= now()
+            "#
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .unwrap()
+        .count
+    }
+
+    /// Extract the one-click unsubscribe link from the headers of a newsletter email.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let headers = body["Headers"].as_array().unwrap();
+        let list_unsubscribe = headers
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .unwrap()["Value"]
+            .as_str()
+            .unwrap();
+        let raw_link = list_unsubscribe
+            .strip_prefix('<')
+            .and_then(|l| l.strip_suffix('>'))
+            .unwrap();
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    /// Extract the link to the preference center from the footer of a newsletter email.
+    pub fn get_preferences_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let raw_link = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .map(|l| l.as_str().to_owned())
+            .find(|l| l.contains("/subscriptions/preferences"))
+            .unwrap();
+        let mut preferences_link = reqwest::Url::parse(&raw_link).unwrap();
+        assert_eq!(preferences_link.host_str().unwrap(), "127.0.0.1");
+        preferences_link.set_port(Some(self.port)).unwrap();
+        preferences_link
+    }
+
+    /// Extract the confirmation links embedded in the request to the email API.
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let get_link = |s: &str| {
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .collect();
+            assert_eq!(links.len(), 1);
+            let raw_link = links[0].as_str().to_owned();
+            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
+            // Let's make sure we don't call random APIs on the web
+            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            confirmation_link.set_port(Some(self.port)).unwrap();
+            confirmation_link
+        };
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
+        ConfirmationLinks { html, plain_text }
+    }
+}
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let subscriber_name = "test".to_string();
     let default_filter_level = "info".to_string();
+    // Spans are not exported, but still carry a trace context.
+    let mut settings = get_configuration()
+        .expect("Failed to read configuration.")
+        .telemetry;
+    settings.otlp_enabled = false;
+    let tracer_provider =
+        build_tracer_provider(&settings).expect("Failed to build the tracer provider.");
+    let tracer = tracer_provider.tracer("test");
+    opentelemetry::global::set_tracer_provider(tracer_provider);
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            tracer,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber =
+            get_subscriber(subscriber_name, default_filter_level, std::io::sink, tracer);
         init_subscriber(subscriber);
     }
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, with a chance to tweak the configuration before the application is built.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
     let email_server = MockServer::start().await;
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration.");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
+        c.email_client.provider = EmailProvider::Postmark;
         c.email_client.base_url = email_server.uri();
+        // Keep the number of requests hitting the mock server predictable:
+        // the retry logic is covered by the email client's own tests.
+        c.email_client.retry.max_attempts = 1;
+        // Retry failed deliveries straight away
+        c.issue_delivery_worker.retry_delay_seconds = 0;
+        configure(&mut c);
         c
     };
     configure_database(&configuration.database).await;
     let application = Application::build(configuration.clone())
         .await
         .expect("Failed to build application.");
-    let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
-    TestApp {
+    let application_port = application.port();
+    let address = format!("http://127.0.0.1:{}", application_port);
+    let shutdown = application.shutdown_handle();
+    let application = tokio::spawn(application.run_until_stopped());
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let test_app = TestApp {
         address,
+        port: application_port,
         db_pool: get_connection_pool(&configuration.database),
+        database: configuration.database.clone(),
         email_server,
-    }
+        issue_delivery_worker: IssueDeliveryWorker::build(configuration.clone()),
+        retention_worker: RetentionWorker::build(configuration.clone()),
+        test_user: TestUser::generate(),
+        api_client,
+        webhook_secret: configuration
+            .email_client
+            .webhook_secret
+            .expose_secret()
+            .to_owned(),
+        form_token_secret: configuration
+            .application
+            .bot_protection
+            .form_token_secret
+            .clone(),
+        shutdown,
+        application,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
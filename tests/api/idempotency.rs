@@ -0,0 +1,308 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::configuration::RateLimitSettings;
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn retried_subscriptions_with_the_same_key_are_processed_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = Uuid::new_v4().to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let first = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    let second = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_with_the_same_key_are_processed_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = Uuid::new_v4().to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        // Long enough for the second request to arrive while the first is in flight
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let first = app.post_subscriptions_with_idempotency_key(body.into(), &idempotency_key);
+    let second = app.post_subscriptions_with_idempotency_key(body.into(), &idempotency_key);
+    let (first, second) = tokio::join!(first, second);
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn requests_with_different_keys_are_processed_independently() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let first = app
+        .post_subscriptions_with_idempotency_key(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+            &Uuid::new_v4().to_string(),
+        )
+        .await;
+    let second = app
+        .post_subscriptions_with_idempotency_key(
+            "name=jeremy&email=jeremy%40example.com".into(),
+            &Uuid::new_v4().to_string(),
+        )
+        .await;
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn server_errors_are_not_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = Uuid::new_v4().to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let first = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    let n_saved_responses = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM idempotency"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    // Assert
+    assert_eq!(first.status().as_u16(), 500);
+    assert_eq!(n_saved_responses, 0);
+}
+
+#[tokio::test]
+async fn rate_limited_requests_can_be_retried_with_the_same_key() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.rate_limiting.per_email = RateLimitSettings {
+            capacity: 1,
+            refill_interval_seconds: 1,
+        }
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let idempotency_key = Uuid::new_v4().to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let rate_limited = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    assert_eq!(rate_limited.status().as_u16(), 429);
+    // Act
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let retried = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    // Assert
+    assert_eq!(retried.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn expired_keys_are_deleted_by_the_retention_worker() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=jeremy&email=jeremy%40example.com",
+    ] {
+        app.post_subscriptions_with_idempotency_key(body.into(), &Uuid::new_v4().to_string())
+            .await;
+    }
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.post_subscriptions_with_idempotency_key(
+        "name=octavia&email=octavia%40example.com".into(),
+        &Uuid::new_v4().to_string(),
+    )
+    .await;
+    // Act
+    let n_deleted = app
+        .retention_worker
+        .delete_expired_idempotency_keys()
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(n_deleted, 2);
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let too_long_key = "a".repeat(50);
+    let test_cases = vec![("", "empty key"), (too_long_key.as_str(), "key too long")];
+    for (idempotency_key, description) in test_cases {
+        // Act
+        let response = app
+            .post_subscriptions_with_idempotency_key(body.into(), idempotency_key)
+            .await;
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the idempotency key was an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn responses_are_not_replayed_to_other_callers() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let subscriber_id = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["subscriber_id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    app.login_test_user().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let update = |client: &reqwest::Client| {
+        client
+            .patch(format!(
+                "{}/admin/subscribers/{}",
+                app.address, subscriber_id
+            ))
+            .header("Idempotency-Key", &idempotency_key)
+            .json(&serde_json::json!({ "name": "Ursula K. Le Guin" }))
+            .send()
+    };
+    // Act
+    let admin_response = update(&app.api_client).await.unwrap();
+    let anonymous_response = update(
+        &reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap(),
+    )
+    .await
+    .unwrap();
+    // Assert
+    assert_eq!(admin_response.status().as_u16(), 200);
+    assert_eq!(anonymous_response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_same_key_can_be_used_on_different_endpoints() {
+    // Arrange
+    let app = spawn_app().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let first = app
+        .post_subscriptions_with_idempotency_key(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+            &idempotency_key,
+        )
+        .await;
+    let second = reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .header("Idempotency-Key", &idempotency_key)
+        .json(&serde_json::json!({ "name": "jeremy", "email": "jeremy@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert!(second.json::<serde_json::Value>().await.is_ok());
+}
+
+#[tokio::test]
+async fn anonymous_requests_with_a_different_body_are_not_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=jeremy&email=jeremy%40example.com",
+    ] {
+        let response = app
+            .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+            .await;
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // Mock verifies on Drop that both confirmation emails were sent
+}
@@ -0,0 +1,269 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::problem_details::ProblemDetails;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn create_list(app: &TestApp, slug: &str) {
+    app.post_lists(serde_json::json!({
+        "slug": slug,
+        "name": "Engineering digest"
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+/// Subscribe to `list_slug` and follow the link in the confirmation email.
+async fn subscribe_and_confirm(app: &TestApp, list_slug: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_list_subscriptions(list_slug, SUBSCRIBER.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn membership_status(app: &TestApp, list_slug: &str) -> Option<String> {
+    sqlx::query!(
+        r#"
+            SELECT status
+            FROM list_memberships
+            JOIN lists ON lists.id = list_memberships.list_id
+            WHERE lists.slug = $1
+        "#,
+        list_slug
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|m| m.status)
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn creating_a_list_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/lists", &app.address))
+        .json(&serde_json::json!({"slug": "engineering-digest", "name": "Digest"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn lists_need_a_valid_and_unique_slug() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "engineering-digest").await;
+    let test_cases = vec![
+        ("engineering-digest", 409, "/problems/list-already-exists"),
+        ("newsletter", 409, "/problems/list-already-exists"),
+        ("Product Updates", 400, "/problems/invalid-list"),
+    ];
+    for (slug, expected_status, expected_problem_type) in test_cases {
+        // Act
+        let response = app
+            .post_lists(serde_json::json!({"slug": slug, "name": "Some list"}))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), expected_status);
+        let problem: ProblemDetails = response.json().await.unwrap();
+        assert_eq!(problem.problem_type, expected_problem_type);
+    }
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_list_subscriptions("product-updates", SUBSCRIBER.into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: ProblemDetails = response.json().await.unwrap();
+    assert_eq!(problem.problem_type, "/problems/unknown-list");
+}
+
+#[tokio::test]
+async fn confirmation_is_tracked_per_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "engineering-digest").await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_list_subscriptions("engineering-digest", SUBSCRIBER.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        membership_status(&app, "newsletter").await.unwrap(),
+        "confirmed"
+    );
+    assert_eq!(
+        membership_status(&app, "engineering-digest").await.unwrap(),
+        "pending_confirmation"
+    );
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn the_confirmation_link_confirms_the_list_it_was_sent_for() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "engineering-digest").await;
+
+    // Act
+    subscribe_and_confirm(&app, "engineering-digest").await;
+
+    // Assert
+    assert_eq!(
+        membership_status(&app, "engineering-digest").await.unwrap(),
+        "confirmed"
+    );
+    assert!(membership_status(&app, "newsletter").await.is_none());
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_members_of_their_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "engineering-digest").await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_list_newsletters("engineering-digest", newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_list_newsletters("product-updates", newsletter_request_body())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: ProblemDetails = response.json().await.unwrap();
+    assert_eq!(problem.problem_type, "/problems/unknown-list");
+}
+
+#[tokio::test]
+async fn unsubscribing_only_leaves_the_list_of_the_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "engineering-digest").await;
+    create_confirmed_subscriber(&app).await;
+    subscribe_and_confirm(&app, "engineering-digest").await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_list_newsletters("engineering-digest", newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        membership_status(&app, "engineering-digest").await.unwrap(),
+        "unsubscribed"
+    );
+    assert_eq!(
+        membership_status(&app, "newsletter").await.unwrap(),
+        "confirmed"
+    );
+}
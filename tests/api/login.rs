@@ -0,0 +1,107 @@
+use secrecy::Secret;
+use zero2prod::configuration::InitialAdminSettings;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act - Part 1 - Try to login
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // Act - Part 3 - Reload the login page
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("<p><i>Authentication failed.</i></p>"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_session_is_renewed_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let session_cookie = |response: &reqwest::Response| {
+        response
+            .cookies()
+            .find(|c| c.name() == "id")
+            .map(|c| c.value().to_owned())
+            .expect("No session cookie was set.")
+    };
+    // Act
+    let first_login = app.post_login(&login_body).await;
+    let second_login = app.post_login(&login_body).await;
+    // Assert
+    assert_ne!(session_cookie(&first_login), session_cookie(&second_login));
+    let n_sessions = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_sessions, 1);
+}
+
+#[tokio::test]
+async fn no_user_exists_out_of_the_box() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let n_users = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM users"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    // Assert - Only the test user
+    assert_eq!(n_users, 1);
+}
+
+#[tokio::test]
+async fn the_initial_admin_can_log_in() {
+    // Arrange
+    let password = uuid::Uuid::new_v4().to_string();
+    let app = spawn_app_with(|c| {
+        c.application.initial_admin = Some(InitialAdminSettings {
+            username: "root".into(),
+            password: Secret::new(password.clone()),
+        })
+    })
+    .await;
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "root",
+            "password": &password
+        }))
+        .await;
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
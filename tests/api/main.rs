@@ -1,3 +1,21 @@
+mod admin_dashboard;
+mod admin_subscribers;
+mod bot_protection;
+mod change_password;
 mod health_check;
 mod helpers;
+mod idempotency;
+mod lists;
+mod login;
+mod metrics;
+mod newsletters;
+mod rate_limiting;
+mod shutdown;
 mod subscriptions;
+mod subscriptions_api;
+mod subscriptions_confirm;
+mod subscriptions_me;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod tenants;
+mod webhooks;
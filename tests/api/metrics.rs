@@ -0,0 +1,86 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn get_metrics(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn metrics_are_exposed_in_the_prometheus_text_format() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let response = get_metrics(&app).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/plain; version=0.0.4"
+    );
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"db_pool_connections{state="idle"}"#));
+    assert!(body.contains(r#"db_pool_connections{state="active"}"#));
+    assert!(body.contains("db_pool_acquire_duration_seconds"));
+}
+
+#[tokio::test]
+async fn requests_are_counted_per_route_pattern() {
+    // Arrange
+    let app = spawn_app().await;
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknowntoken",
+        &app.address
+    ))
+    .await
+    .unwrap();
+    reqwest::get(format!(
+        "{}/not-a-route/{}",
+        &app.address,
+        uuid::Uuid::new_v4()
+    ))
+    .await
+    .unwrap();
+    // Act
+    let body = get_metrics(&app).await.text().await.unwrap();
+    // Assert
+    assert!(
+        body.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#)
+    );
+    assert!(body.contains(
+        r#"http_requests_total{method="GET",route="/subscriptions/confirm",status="401"}"#
+    ));
+    assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"}"#));
+    assert!(body
+        .contains(r#"http_request_duration_seconds_bucket{method="GET",route="/health_check","#));
+}
+
+#[tokio::test]
+async fn email_deliveries_are_counted_by_outcome_and_provider_status_code() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    // Act
+    let body = get_metrics(&app).await.text().await.unwrap();
+    // Assert
+    assert!(body.contains(r#"email_sends_total{outcome="sent",provider="postmark"}"#));
+    assert!(
+        body.contains(r#"email_provider_responses_total{provider="postmark",status_code="200"}"#)
+    );
+}
@@ -0,0 +1,273 @@
+use reqwest::Client;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::{RateLimitBackend, RateLimitSettings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+fn limit(capacity: u32) -> RateLimitSettings {
+    RateLimitSettings {
+        capacity,
+        refill_interval_seconds: 3600,
+    }
+}
+
+async fn accept_all_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+fn subscription_body(i: usize) -> String {
+    format!("name=le%20guin&email=ursula_le_guin_{}%40gmail.com", i)
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_once_the_client_exceeds_its_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(2));
+    })
+    .await;
+    accept_all_emails(&app).await;
+    for i in 0..2 {
+        let response = app.post_subscriptions(subscription_body(i)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Act
+    let response = app.post_subscriptions(subscription_body(2)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/rate-limited");
+}
+
+#[tokio::test]
+async fn the_same_address_cannot_be_emailed_over_its_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.rate_limiting.per_email = limit(1)).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(body.replace("ursula_le_guin", "URSULA_LE_GUIN"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn routes_without_a_limit_are_not_rate_limited() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting.routes.clear();
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(1));
+    })
+    .await;
+
+    for _ in 0..5 {
+        // Act
+        let response = Client::new()
+            .get(format!("{}/health_check", &app.address))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_limited_separately() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting.trusted_proxies = 1;
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(1));
+    })
+    .await;
+    accept_all_emails(&app).await;
+    let subscribe_from = |client_ip: &'static str, i: usize| {
+        Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client_ip)
+            .body(subscription_body(i))
+            .send()
+    };
+    let response = subscribe_from("203.0.113.1", 0).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let limited = subscribe_from("203.0.113.1", 1).await.unwrap();
+    let other_client = subscribe_from("203.0.113.2", 2).await.unwrap();
+
+    // Assert
+    assert_eq!(limited.status().as_u16(), 429);
+    assert_eq!(other_client.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn clients_cannot_dodge_the_limit_by_forging_forwarded_hops() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting.trusted_proxies = 1;
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(1));
+    })
+    .await;
+    accept_all_emails(&app).await;
+    // The client picks the first hop, our proxy appends the address it sees.
+    let subscribe_as = |forged_ip: &'static str, i: usize| {
+        Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("{}, 203.0.113.1", forged_ip))
+            .body(subscription_body(i))
+            .send()
+    };
+    let response = subscribe_as("198.51.100.1", 0).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = subscribe_as("198.51.100.2", 1).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn limits_can_be_kept_in_postgres() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting.backend = RateLimitBackend::Postgres;
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(1));
+    })
+    .await;
+    accept_all_emails(&app).await;
+    let response = app.post_subscriptions(subscription_body(0)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = app.post_subscriptions(subscription_body(1)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let n_buckets = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM rate_limit_buckets"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    // One for the client on the route, one for the address that was emailed.
+    assert_eq!(n_buckets, 2);
+    let n_addresses =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM rate_limit_buckets WHERE key LIKE '%@%'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    // Keys must not give the address away.
+    assert_eq!(n_addresses, 0);
+}
+
+#[tokio::test]
+async fn clients_that_bypassed_the_trusted_proxy_are_limited_by_their_peer_address() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting.trusted_proxies = 1;
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(1));
+    })
+    .await;
+    accept_all_emails(&app).await;
+    // No `X-Forwarded-For`: the request reached us directly.
+    let response = app.post_subscriptions(subscription_body(0)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act
+    let response = app.post_subscriptions(subscription_body(1)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn full_buckets_are_deleted_by_the_retention_worker() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        let rate_limiting = &mut c.application.rate_limiting;
+        rate_limiting.backend = RateLimitBackend::Postgres;
+        rate_limiting
+            .routes
+            .insert("/subscriptions".into(), limit(2));
+    })
+    .await;
+    accept_all_emails(&app).await;
+    for i in 0..2 {
+        let response = app.post_subscriptions(subscription_body(i)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // The route bucket takes two hours to fill up: leave it half-used.
+    sqlx::query!(
+        r#"
+        UPDATE rate_limit_buckets
+        SET updated_at = CASE WHEN key LIKE 'route:%' THEN now() - interval '1 hour'
+                              ELSE now() - interval '3 hours' END
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let n_deleted = app
+        .retention_worker
+        .delete_full_rate_limit_buckets()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(n_deleted, 2);
+    let remaining = sqlx::query!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert!(remaining[0].key.starts_with("route:"));
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn readiness_fails_as_soon_as_shutdown_is_triggered() {
+    // Arrange
+    let mut app = spawn_app_with(|c| c.shutdown.grace_period_milliseconds = 1000).await;
+
+    // Act
+    app.shutdown.trigger();
+    let response = Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    app.shut_down().await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_does_not_wait_for_idle_workers() {
+    // Arrange
+    let mut app = spawn_app().await;
+
+    // Act
+    let outcome = tokio::time::timeout(Duration::from_secs(5), app.shut_down())
+        .await
+        .expect("Shutdown waited for the workers' idle poll interval.");
+
+    // Assert
+    assert!(outcome.is_ok());
+    let refused = Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await;
+    assert!(refused.is_err());
+}
+
+#[tokio::test]
+async fn in_flight_requests_complete_before_the_application_stops() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let request = tokio::spawn(
+        Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send(),
+    );
+    // Let the request reach the email provider.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Act
+    app.shut_down().await.unwrap();
+
+    // Assert
+    let response = request.await.unwrap().expect("The request was cut off.");
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn the_delivery_worker_finishes_its_current_email_before_stopping() {
+    // Arrange
+    let mut app =
+        spawn_app_with(|c| c.issue_delivery_worker.idle_poll_interval_milliseconds = 10).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    // Let the application's worker pick the task up.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Act
+    app.shut_down().await.unwrap();
+
+    // Assert
+    let n_pending = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_pending, 0);
+    // Mock verifies on Drop that the email went out exactly once.
+}
@@ -0,0 +1,174 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::problem_details::ProblemDetails;
+
+async fn accept_confirmation_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_json_body() {
+    // Arrange
+    let app = spawn_app().await;
+    accept_confirmation_emails(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn subscribe_returns_the_subscriber_as_json_when_asked_to() {
+    // Arrange
+    let app = spawn_app().await;
+    accept_confirmation_emails(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Accept", "application/json")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(body["subscriber_id"], saved.id.to_string());
+    assert_eq!(body["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn browsers_still_get_an_empty_response() {
+    // Arrange
+    let app = spawn_app().await;
+    accept_confirmation_emails(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header(
+            "Accept",
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        )
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn the_versioned_api_always_responds_with_json() {
+    // Arrange
+    let app = spawn_app().await;
+    accept_confirmation_emails(&app).await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(Uuid::parse_str(body["subscriber_id"].as_str().unwrap()).is_ok());
+    assert_eq!(body["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn the_api_does_not_reveal_that_an_address_is_already_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn the_api_reports_an_error_for_each_invalid_field() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "",
+            "email": "not-an-email"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: ProblemDetails = response.json().await.unwrap();
+    assert_eq!(problem.problem_type, "/problems/invalid-subscriber");
+    assert_eq!(problem.errors.len(), 2);
+    assert!(problem.errors.contains_key("name"));
+    assert!(problem.errors.contains_key("email"));
+}
+
+#[tokio::test]
+async fn the_api_rejects_malformed_json() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", &app.address))
+        .header("Content-Type", "application/json")
+        .body("{\"name\": \"le guin\"")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: ProblemDetails = response.json().await.unwrap();
+    assert_eq!(problem.problem_type, "/problems/invalid-request");
+}
@@ -0,0 +1,453 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::RateLimitBackend;
+use zero2prod::problem_details::ProblemDetails;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, spawn_app_with, TestApp};
+
+/// Deliver an issue to a new confirmed subscriber and return the token of the links
+/// in the email they received.
+async fn confirmed_subscriber_token(app: &TestApp) -> String {
+    create_confirmed_subscriber(app).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_unsubscribe_link(&email_request)
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn post_data_request(app: &TestApp, email: &str, purpose: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/me/requests", app.address))
+        .json(&serde_json::json!({ "email": email, "purpose": purpose }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Ask for a link to export or erase the data of the confirmed subscriber, and return
+/// the link that was emailed to them.
+async fn request_data_link(app: &TestApp, purpose: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = post_data_request(app, "ursula_le_guin@gmail.com", purpose).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let raw_link = linkify::LinkFinder::new()
+        .links(body["TextBody"].as_str().unwrap())
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains("/subscriptions/me/"))
+        .unwrap();
+    let mut link = reqwest::Url::parse(&raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn request_data_token(app: &TestApp, purpose: &str) -> String {
+    request_data_link(app, purpose)
+        .await
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn export(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/subscriptions/me/export", app.address))
+        .query(&[("token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn erase(app: &TestApp, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .delete(format!("{}/subscriptions/me", app.address))
+        .query(&[("token", token)])
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn tenant_id(app: &TestApp, slug: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM tenants WHERE slug = $1", slug)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+/// Report the delivery of an email sent to our subscriber on behalf of `tenant_id`.
+async fn post_delivery_event(app: &TestApp, tenant_id: Uuid) {
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Delivery",
+        "Recipient": "ursula_le_guin@gmail.com",
+        "DeliveredAt": "2023-12-10T16:33:54.9070259Z",
+        "Metadata": {"tenant_id": tenant_id},
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+#[tokio::test]
+async fn data_subject_requests_require_a_valid_token() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // Act
+    let export_response = export(&app, "not-a-token").await;
+    let erase_response = erase(&app, "not-a-token").await;
+    // Assert
+    for response in [export_response, erase_response] {
+        assert_eq!(response.status().as_u16(), 401);
+        let problem: ProblemDetails = response.json().await.unwrap();
+        assert_eq!(problem.problem_type, "/problems/invalid-subscriber-token");
+    }
+}
+
+#[tokio::test]
+async fn the_links_in_newsletters_do_not_grant_access_to_the_data() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+    // Act
+    let export_response = export(&app, &token).await;
+    let erase_response = erase(&app, &token).await;
+    // Assert
+    assert_eq!(export_response.status().as_u16(), 401);
+    assert_eq!(erase_response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn tokens_only_grant_the_request_they_were_issued_for() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let export_token = request_data_token(&app, "export").await;
+    let erasure_token = request_data_token(&app, "erasure").await;
+    // Act
+    let export_response = export(&app, &erasure_token).await;
+    let erase_response = erase(&app, &export_token).await;
+    // Assert
+    assert_eq!(export_response.status().as_u16(), 401);
+    assert_eq!(erase_response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscription_tokens.data_request_ttl_minutes = 0).await;
+    create_confirmed_subscriber(&app).await;
+    let export_token = request_data_token(&app, "export").await;
+    let erasure_token = request_data_token(&app, "erasure").await;
+    // Act
+    let export_response = export(&app, &export_token).await;
+    let erase_response = erase(&app, &erasure_token).await;
+    // Assert
+    assert_eq!(export_response.status().as_u16(), 401);
+    assert_eq!(erase_response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn requests_for_unknown_addresses_are_accepted_but_send_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = post_data_request(&app, "nobody@example.com", "export").await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    // Mock verifies on Drop that we haven't sent any email
+}
+
+#[tokio::test]
+async fn the_erasure_link_asks_for_confirmation_before_erasing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let erasure_link = request_data_link(&app, "erasure").await;
+    // Act - Part 1 - Follow the link
+    let form = reqwest::get(erasure_link.clone()).await.unwrap();
+    assert_eq!(form.status().as_u16(), 200);
+    assert!(form.text().await.unwrap().contains("Erase my data"));
+    let n_erased = || async {
+        sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM subscriptions WHERE erased_at IS NOT NULL"#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+    };
+    assert_eq!(n_erased().await, 0);
+    // Act - Part 2 - Submit the form
+    let response = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_erased().await, 1);
+}
+
+#[tokio::test]
+async fn the_export_contains_all_the_data_tied_to_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = request_data_token(&app, "export").await;
+    let tenant_id = tenant_id(&app, "default").await;
+    post_delivery_event(&app, tenant_id).await;
+    // Act
+    let response = export(&app, &token).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscriber"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(data["subscriber"]["name"], "le guin");
+    assert!(data["subscriber"]["subscribed_at"].is_string());
+    assert_eq!(data["lists"][0]["list"], "newsletter");
+    assert_eq!(data["lists"][0]["status"], "confirmed");
+    // The confirmation token was consumed when the subscription was confirmed.
+    assert!(data["subscription_tokens"].as_array().unwrap().is_empty());
+    assert_eq!(data["email_events"].as_array().unwrap().len(), 1);
+    assert_eq!(data["email_events"][0]["record_type"], "Delivery");
+    assert_eq!(
+        data["email_events"][0]["payload"]["Recipient"],
+        "ursula_le_guin@gmail.com"
+    );
+    assert!(data["preferences"].is_null());
+}
+
+#[tokio::test]
+async fn erasure_strips_the_personal_data_but_keeps_the_subscription_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = request_data_token(&app, "erasure").await;
+    // Act
+    let response = erase(&app, &token).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    let saved = sqlx::query!("SELECT email, name, erased_at FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert!(!saved[0].email.contains("ursula"));
+    assert!(saved[0].name.is_empty());
+    assert!(saved[0].erased_at.is_some());
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+    let status = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn the_events_of_other_tenants_are_neither_exported_nor_erased() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.rate_limiting.per_email.capacity = 10).await;
+    create_confirmed_subscriber(&app).await;
+    let other_tenant_id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO tenants (id, slug, created_at) VALUES ($1, 'acme', now())",
+        other_tenant_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    post_delivery_event(&app, tenant_id(&app, "default").await).await;
+    post_delivery_event(&app, other_tenant_id).await;
+    let export_token = request_data_token(&app, "export").await;
+    let erasure_token = request_data_token(&app, "erasure").await;
+    // Act
+    let data: serde_json::Value = export(&app, &export_token).await.json().await.unwrap();
+    erase(&app, &erasure_token)
+        .await
+        .error_for_status()
+        .unwrap();
+    // Assert
+    assert_eq!(data["email_events"].as_array().unwrap().len(), 1);
+    let remaining = sqlx::query!("SELECT tenant_id FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].tenant_id, Some(other_tenant_id));
+}
+
+#[tokio::test]
+async fn unattributed_events_are_exported_and_erased() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.rate_limiting.per_email.capacity = 10).await;
+    create_confirmed_subscriber(&app).await;
+    app.post_postmark_webhook(&serde_json::json!({
+        "RecordType": "Delivery",
+        "Recipient": "ursula_le_guin@gmail.com",
+        "DeliveredAt": "2023-12-10T16:33:54.9070259Z",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    let export_token = request_data_token(&app, "export").await;
+    let erasure_token = request_data_token(&app, "erasure").await;
+    // Act
+    let data: serde_json::Value = export(&app, &export_token).await.json().await.unwrap();
+    erase(&app, &erasure_token)
+        .await
+        .error_for_status()
+        .unwrap();
+    // Assert
+    assert_eq!(data["email_events"].as_array().unwrap().len(), 1);
+    let n_events = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_events, 0);
+}
+
+#[tokio::test]
+async fn erasure_forgets_the_rate_limit_of_the_address() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.application.rate_limiting.backend = RateLimitBackend::Postgres;
+        c.application.rate_limiting.per_email.capacity = 10;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    let token = request_data_token(&app, "erasure").await;
+    // Act
+    erase(&app, &token).await.error_for_status().unwrap();
+    // Assert
+    let n_buckets = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM rate_limit_buckets WHERE key LIKE 'email:%'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_buckets, 0);
+}
+
+#[tokio::test]
+async fn erased_subscribers_are_gone_for_good() {
+    // Arrange
+    let app = spawn_app_with(|c| c.application.rate_limiting.per_email.capacity = 10).await;
+    create_confirmed_subscriber(&app).await;
+    let export_token = request_data_token(&app, "export").await;
+    let token = request_data_token(&app, "erasure").await;
+    erase(&app, &token).await.error_for_status().unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let export_response = export(&app, &export_token).await;
+    let erase_response = erase(&app, &token).await;
+    let request_response = post_data_request(&app, "ursula_le_guin@gmail.com", "export").await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    // Assert
+    assert_eq!(export_response.status().as_u16(), 401);
+    assert_eq!(erase_response.status().as_u16(), 401);
+    assert_eq!(request_response.status().as_u16(), 202);
+    // Mock verifies on Drop that we haven't sent the newsletter email, nor a new link
+}
+
+#[tokio::test]
+async fn an_erased_address_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = request_data_token(&app, "erasure").await;
+    erase(&app, &token).await.error_for_status().unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let n_subscriptions = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscriptions, 2);
+}
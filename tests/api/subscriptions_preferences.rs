@@ -0,0 +1,428 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::problem_details::ProblemDetails;
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+
+/// Publish an issue, optionally about `topic`, and deliver everything that is due.
+async fn publish_and_dispatch(app: &TestApp, title: &str, topic: Option<&str>) {
+    app.post_newsletters(serde_json::json!({
+        "title": title,
+        "topic": topic,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+/// Deliver an issue to a new confirmed subscriber and return the link to their preferences.
+async fn confirmed_subscriber_preferences_link(app: &TestApp) -> reqwest::Url {
+    create_confirmed_subscriber(app).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    publish_and_dispatch(app, "Welcome", None).await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_preferences_link(&email_request)
+}
+
+/// The same link, on the JSON API.
+fn api_link(link: &reqwest::Url) -> reqwest::Url {
+    let mut link = link.clone();
+    link.set_path("/api/v1/subscriptions/preferences");
+    link
+}
+
+async fn create_topic(app: &TestApp, slug: &str) -> String {
+    let response = app
+        .post_topics(
+            "newsletter",
+            serde_json::json!({ "slug": slug, "name": slug.to_uppercase() }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let topic: serde_json::Value = response.json().await.unwrap();
+    topic["id"].as_str().unwrap().to_owned()
+}
+
+async fn get_preferences_json(app: &TestApp, link: &reqwest::Url) -> serde_json::Value {
+    app.api_client
+        .get(api_link(link))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn update_preferences(
+    app: &TestApp,
+    link: &reqwest::Url,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(api_link(link))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletter_emails_link_to_the_preferences_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    // Assert
+    let response = app.api_client.get(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains(r#"value="le guin""#));
+}
+
+#[tokio::test]
+async fn preferences_require_a_valid_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec!["", "?token=", "?token=not-a-token"];
+    for query in test_cases {
+        // Act
+        let response = app
+            .api_client
+            .get(format!(
+                "{}/subscriptions/preferences{}",
+                app.address, query
+            ))
+            .send()
+            .await
+            .unwrap();
+        // Assert
+        assert!(
+            response.status().is_client_error(),
+            "Query: {:?}, status: {}",
+            query,
+            response.status()
+        );
+    }
+}
+
+#[tokio::test]
+async fn preferences_are_returned_as_json() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    let topic_id = create_topic(&app, "rust").await;
+    // Act
+    let preferences = get_preferences_json(&app, &link).await;
+    // Assert
+    assert_eq!(preferences["name"], "le guin");
+    assert_eq!(preferences["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(preferences["delivery_frequency"], "immediate");
+    assert_eq!(
+        preferences["topics"],
+        serde_json::json!([{
+            "id": topic_id,
+            "list": "newsletter",
+            "name": "RUST",
+            "subscribed": true
+        }])
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name_through_the_form() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    // Act - Part 1 - Submit the form
+    let response = app
+        .api_client
+        .post(link.clone())
+        .form(&[
+            ("name", "Ursula K. Le Guin"),
+            ("delivery_frequency", "immediate"),
+            ("topics", ""),
+        ])
+        .send()
+        .await
+        .unwrap();
+    // Assert - Part 1
+    let location = format!("{}?{}", link.path(), link.query().unwrap());
+    assert_is_redirect_to(&response, &location);
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    // Assert - Part 2
+    assert!(html_page.contains("<p><i>Your preferences have been saved.</i></p>"));
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    let test_cases = vec![
+        (serde_json::json!({ "name": "" }), "name"),
+        (
+            serde_json::json!({ "delivery_frequency": "monthly" }),
+            "delivery_frequency",
+        ),
+        (
+            serde_json::json!({ "topics": [uuid::Uuid::new_v4()] }),
+            "topics",
+        ),
+        (serde_json::json!({ "topics": ["rust"] }), "topics"),
+    ];
+    for (body, field) in test_cases {
+        // Act
+        let response = update_preferences(&app, &link, body.clone()).await;
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "Body: {}", body);
+        let problem: ProblemDetails = response.json().await.unwrap();
+        assert_eq!(problem.problem_type, "/problems/invalid-preferences");
+        assert!(problem.errors.contains_key(field), "Body: {}", body);
+    }
+    let preferences = get_preferences_json(&app, &link).await;
+    assert_eq!(preferences["name"], "le guin");
+}
+
+#[tokio::test]
+async fn subscribers_do_not_get_the_topics_they_opted_out_of() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    let rust = create_topic(&app, "rust").await;
+    create_topic(&app, "go").await;
+    let response = update_preferences(&app, &link, serde_json::json!({ "topics": [rust] })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let preferences: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(preferences["topics"][0]["name"], "GO");
+    assert_eq!(preferences["topics"][0]["subscribed"], false);
+    assert_eq!(preferences["topics"][1]["name"], "RUST");
+    assert_eq!(preferences["topics"][1]["subscribed"], true);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // Everything but the issue about Go
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_and_dispatch(&app, "About Go", Some("go")).await;
+    publish_and_dispatch(&app, "About Rust", Some("rust")).await;
+    publish_and_dispatch(&app, "About everything", None).await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the issue about Go
+}
+
+#[tokio::test]
+async fn subscribers_get_the_topics_created_after_they_saved_their_preferences() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    update_preferences(
+        &app,
+        &link,
+        serde_json::json!({ "delivery_frequency": "immediate" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let rust = create_topic(&app, "rust").await;
+    let preferences = get_preferences_json(&app, &link).await;
+    assert_eq!(preferences["topics"][0]["id"], rust);
+    assert_eq!(preferences["topics"][0]["subscribed"], true);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_and_dispatch(&app, "About Rust", Some("rust")).await;
+
+    // Assert
+    // Mock verifies on Drop that the issue about the new topic was sent
+}
+
+#[tokio::test]
+async fn weekly_digest_subscribers_get_the_issues_bundled_together() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    update_preferences(
+        &app,
+        &link,
+        serde_json::json!({ "delivery_frequency": "weekly_digest" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish during the week
+    publish_and_dispatch(&app, "First issue", None).await;
+    publish_and_dispatch(&app, "Second issue", None).await;
+
+    // Assert - Part 1
+    let held = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue WHERE digest AND execute_after > now()"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(held.count, 2);
+
+    // Act - Part 2 - The digest is due
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2
+    // Mock verifies on Drop that the two issues went out in a single email
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Newsletter: your weekly digest");
+    let text = body["TextBody"].as_str().unwrap();
+    let first = text.find("First issue").unwrap();
+    let second = text.find("Second issue").unwrap();
+    assert!(first < second);
+}
+
+#[tokio::test]
+async fn going_back_to_immediate_delivery_releases_the_held_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    let link = confirmed_subscriber_preferences_link(&app).await;
+    update_preferences(
+        &app,
+        &link,
+        serde_json::json!({ "delivery_frequency": "weekly_digest" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    publish_and_dispatch(&app, "Held issue", None).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    update_preferences(
+        &app,
+        &link,
+        serde_json::json!({ "delivery_frequency": "immediate" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Held issue");
+}
+
+#[tokio::test]
+async fn publishing_about_an_unknown_topic_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "topic": "unknown",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: ProblemDetails = response.json().await.unwrap();
+    assert_eq!(problem.problem_type, "/problems/unknown-topic");
+}
+
+#[tokio::test]
+async fn topics_are_unique_within_a_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_topic(&app, "rust").await;
+    // Act
+    let response = app
+        .post_topics(
+            "newsletter",
+            serde_json::json!({ "slug": "rust", "name": "Rust again" }),
+        )
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let response = app
+        .post_topics(
+            "missing",
+            serde_json::json!({ "slug": "rust", "name": "Rust" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,302 @@
+use uuid::Uuid;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::problem_details::ProblemDetails;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Store a tenant with its own sender and provider token, along with its default list.
+/// The test user is a member of it.
+async fn create_tenant(app: &TestApp, slug: &str, host: Option<&str>) -> Uuid {
+    let tenant_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+            INSERT INTO tenants (id, slug, host, sender_email, email_authorization_token, created_at)
+            VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        tenant_id,
+        slug,
+        host,
+        format!("news@{}.example.com", slug),
+        format!("{}-token", slug),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.join_tenant(&app.db_pool, slug).await;
+    reqwest::Client::new()
+        .post(format!("{}/tenants/{}/lists", &app.address, slug))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({"slug": "newsletter", "name": "Acme news"}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    tenant_id
+}
+
+async fn post_tenant_subscriptions(app: &TestApp, tenant_slug: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/tenants/{}/subscriptions",
+            &app.address, tenant_slug
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(SUBSCRIBER)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn n_subscribers(app: &TestApp, tenant_slug: &str) -> i64 {
+    sqlx::query!(
+        r#"
+            SELECT COUNT(*) AS "count!"
+            FROM subscriptions
+            JOIN tenants ON tenants.id = subscriptions.tenant_id
+            WHERE tenants.slug = $1
+        "#,
+        tenant_slug
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_sent_with_the_identity_of_the_tenant() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tenant(&app, "acme", None).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Postmark-Server-Token", "acme-token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_tenant_subscriptions(&app, "acme").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["From"], "news@acme.example.com");
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(
+        confirmation_links.html.path(),
+        "/tenants/acme/subscriptions/confirm"
+    );
+}
+
+#[tokio::test]
+async fn a_subscriber_confirms_through_the_routes_of_their_tenant() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tenant(&app, "acme", None).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    post_tenant_subscriptions(&app, "acme")
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let mut unprefixed_link = confirmation_links.html.clone();
+    unprefixed_link.set_path("/subscriptions/confirm");
+
+    // Act
+    let wrong_tenant = reqwest::get(unprefixed_link).await.unwrap();
+    let right_tenant = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(wrong_tenant.status().as_u16(), 401);
+    assert_eq!(right_tenant.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_tenant_is_resolved_from_the_host_header() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tenant(&app, "acme", Some("news.acme.example.com")).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Postmark-Server-Token", "acme-token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Host", format!("news.acme.example.com:{}", app.port))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(SUBSCRIBER)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_subscribers(&app, "acme").await, 1);
+    assert_eq!(n_subscribers(&app, "default").await, 0);
+}
+
+#[tokio::test]
+async fn tenants_have_independent_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tenant(&app, "acme", None).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_tenant_subscriptions(&app, "acme").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_subscribers(&app, "default").await, 1);
+    assert_eq!(n_subscribers(&app, "acme").await, 1);
+}
+
+#[tokio::test]
+async fn users_cannot_publish_on_behalf_of_tenants_they_are_not_members_of() {
+    // Arrange
+    let app = spawn_app().await;
+    create_tenant(&app, "acme", None).await;
+    sqlx::query!(
+        r#"
+            DELETE FROM tenant_members
+            USING tenants
+            WHERE tenants.id = tenant_members.tenant_id AND user_id = $1 AND slug = 'acme'
+        "#,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/tenants/acme/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn unknown_tenants_are_rejected_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = post_tenant_subscriptions(&app, "acme").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: ProblemDetails = response.json().await.unwrap();
+    assert_eq!(problem.problem_type, "/problems/unknown-tenant");
+}
+
+#[tokio::test]
+async fn newsletters_are_sent_with_the_identity_of_the_tenant() {
+    // Arrange
+    let app = spawn_app().await;
+    let tenant_id = create_tenant(&app, "acme", None).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    post_tenant_subscriptions(&app, "acme")
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/tenants/acme/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let newsletter_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(
+        newsletter_request
+            .headers
+            .get(&"X-Postmark-Server-Token".into())
+            .unwrap()
+            .as_str(),
+        "acme-token"
+    );
+    let body: serde_json::Value = serde_json::from_slice(&newsletter_request.body).unwrap();
+    assert_eq!(body["From"], "news@acme.example.com");
+    // So that the events Postmark reports about it are attributed to the tenant.
+    assert_eq!(body["Metadata"]["tenant_id"], tenant_id.to_string());
+    let unsubscribe_link = app.get_unsubscribe_link(&newsletter_request);
+    assert_eq!(
+        unsubscribe_link.path(),
+        "/tenants/acme/subscriptions/unsubscribe"
+    );
+}
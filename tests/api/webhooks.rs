@@ -0,0 +1,298 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+fn bounce(email: &str, bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807i64,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Email": email,
+        "Description": "The server was unable to deliver your message.",
+        "BouncedAt": "2023-12-10T16:33:54.9070259Z",
+    })
+}
+
+fn spam_complaint(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "Type": "SpamComplaint",
+        "Email": email,
+        "BouncedAt": "2023-12-10T16:33:54.9070259Z",
+    })
+}
+
+fn delivery(recipient: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Delivery",
+        "Recipient": recipient,
+        "DeliveredAt": "2023-12-10T16:33:54.9070259Z",
+    })
+}
+
+async fn suppressed_reason(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query!(
+        "SELECT reason FROM suppressed_emails WHERE email = $1",
+        email
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| r.reason)
+}
+
+#[tokio::test]
+async fn webhooks_without_the_shared_secret_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email/postmark", &app.address))
+        .json(&bounce("ursula_le_guin@gmail.com", "HardBounce"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let events = sqlx::query!("SELECT email FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+}
+
+#[tokio::test]
+async fn webhooks_with_an_invalid_shared_secret_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email/postmark", &app.address))
+        .header("X-Webhook-Secret", "not-the-secret")
+        .json(&bounce("ursula_le_guin@gmail.com", "HardBounce"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"RecordType": "Bounce"}), "missing email"),
+        (
+            serde_json::json!({"Email": "a@b.com"}),
+            "missing record type",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_postmark_webhook(&body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_hard_bounce_is_recorded_and_suppresses_the_address() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&bounce("Ursula_Le_Guin@gmail.com", "HardBounce"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let event = sqlx::query!("SELECT record_type, email FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(event.email, "ursula_le_guin@gmail.com");
+    assert_eq!(
+        suppressed_reason(&app, "ursula_le_guin@gmail.com").await,
+        Some("hard_bounce".into())
+    );
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_address() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&spam_complaint("ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        suppressed_reason(&app, "ursula_le_guin@gmail.com").await,
+        Some("spam_complaint".into())
+    );
+}
+
+#[tokio::test]
+async fn soft_bounces_and_deliveries_are_recorded_without_suppressing_the_address() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let soft_bounce = app
+        .post_postmark_webhook(&bounce("ursula_le_guin@gmail.com", "SoftBounce"))
+        .await;
+    let delivery = app
+        .post_postmark_webhook(&delivery("ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(soft_bounce.status().as_u16(), 200);
+    assert_eq!(delivery.status().as_u16(), 200);
+    let events = sqlx::query!("SELECT record_type FROM email_events ORDER BY received_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        suppressed_reason(&app, "ursula_le_guin@gmail.com").await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn unsupported_event_types_are_acknowledged_and_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Open",
+            "Recipient": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let events = sqlx::query!("SELECT email FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_postmark_webhook(&bounce("ursula_le_guin@gmail.com", "HardBounce"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_not_sent_to_suppressed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_postmark_webhook(&spam_complaint("ursula_le_guin@gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn addresses_are_only_suppressed_for_the_tenant_that_mailed_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let other_tenant_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO tenants (id, slug, created_at) VALUES ($1, 'acme', now())",
+        other_tenant_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let mut event = bounce("ursula_le_guin@gmail.com", "HardBounce");
+    event["Metadata"] = serde_json::json!({ "tenant_id": other_tenant_id.to_string() });
+    app.post_postmark_webhook(&event)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}